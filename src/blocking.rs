@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use tikv_client::Error;
+use tokio::sync::Notify;
+use tokio::time::{ timeout_at, Duration, Instant };
+use crate::init::{ GLOBAL_BLOCKED, GLOBAL_WAITING_POPS };
+
+// Clients parked by blocking commands (BLPOP/BRPOP, tikv.waitkey) queue on
+// every key they wait for, in arrival order. A write this module commits
+// wakes the waiters of the keys it changed, see wake_key. tikv-client has
+// no way to watch keys for changes, so writes from other Redis nodes, and
+// local ones that do not call wake_key, are picked up by polling: a parked
+// client checks again every BLOCKING_POLL_INTERVAL. Pops go to the oldest
+// waiter of a key first, see Waiter::is_first.
+
+pub const BLOCKING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Longest a blocking command waits, including with a timeout of 0, so a
+// waiter whose disconnect went unnoticed cannot poll TiKV forever
+pub const BLOCKING_MAX_WAIT: Duration = Duration::from_secs(3600);
+
+// One parked client in the queue of a key. Only waiters that pop take
+// part in the FIFO order, tikv.waitkey waiters just watch.
+pub struct Parked {
+    id: u64,
+    cid: u64,
+    pops: bool,
+    notify: Arc<Notify>,
+}
+
+static PARK_ID: AtomicU64 = AtomicU64::new(0);
+
+// Mark client `cid` as waiting in a blocking command. forget_client clears
+// the mark when the connection closes, which stops the wait.
+pub fn start_waiting(cid: u64) {
+    GLOBAL_WAITING_POPS.write().unwrap().insert(cid);
+}
+
+pub fn still_waiting(cid: u64) -> bool {
+    GLOBAL_WAITING_POPS.read().unwrap().contains(&cid)
+}
+
+pub fn stop_waiting(cid: u64) {
+    GLOBAL_WAITING_POPS.write().unwrap().remove(&cid);
+}
+
+// When a wait of `timeout_ms` started now ends, 0 and anything longer
+// than BLOCKING_MAX_WAIT waiting BLOCKING_MAX_WAIT
+pub fn wait_deadline(timeout_ms: u64) -> Result<Instant, Error> {
+    let wait = match timeout_ms {
+        0 => BLOCKING_MAX_WAIT,
+        ms => Duration::from_millis(ms).min(BLOCKING_MAX_WAIT),
+    };
+    Instant::now().checked_add(wait)
+        .ok_or_else(|| Error::StringError(String::from("ERR timeout is out of range")))
+}
+
+// A client parked on some keys, taken out of their queues when dropped
+pub struct Waiter {
+    id: u64,
+    keys: Vec<Vec<u8>>,
+    notify: Arc<Notify>,
+}
+
+// Queue client `cid` on `keys`, behind the clients already waiting there
+pub fn park(cid: u64, keys: Vec<Vec<u8>>, pops: bool) -> Waiter {
+    let id = PARK_ID.fetch_add(1, Ordering::SeqCst);
+    let notify = Arc::new(Notify::new());
+    let mut blocked = GLOBAL_BLOCKED.lock().unwrap();
+    for key in keys.iter() {
+        blocked.entry(key.clone()).or_default().push_back(Parked { id, cid, pops, notify: notify.clone() });
+    }
+    Waiter { id, keys, notify }
+}
+
+impl Waiter {
+    // Whether no waiter that pops has been waiting on `key` for longer
+    pub fn is_first(&self, key: &[u8]) -> bool {
+        let blocked = GLOBAL_BLOCKED.lock().unwrap();
+        blocked.get(key)
+            .and_then(|queue| queue.iter().find(|p| p.pops))
+            .map_or(true, |first| first.id == self.id)
+    }
+
+    // Sleep until a write to one of the keys, the next poll or `deadline`,
+    // whichever comes first. A wakeup sent before this is called is not
+    // lost, it ends the next wait right away.
+    pub async fn wait(&self, deadline: Instant) {
+        let until = (Instant::now() + BLOCKING_POLL_INTERVAL).min(deadline);
+        let _ = timeout_at(until, self.notify.notified()).await;
+    }
+}
+
+impl Drop for Waiter {
+    // Leave the queues and let the next waiter of each key have its turn,
+    // an element this waiter left behind may be for it
+    fn drop(&mut self) {
+        let mut blocked = GLOBAL_BLOCKED.lock().unwrap();
+        for key in self.keys.iter() {
+            if let Some(queue) = blocked.get_mut(key) {
+                queue.retain(|p| p.id != self.id);
+                if let Some(next) = queue.iter().find(|p| p.pops) {
+                    next.notify.notify_one();
+                }
+                if queue.is_empty() {
+                    blocked.remove(key);
+                }
+            }
+        }
+    }
+}
+
+// Wake every client parked on `key`, called once a write to it committed
+pub fn wake_key(key: &[u8]) {
+    if let Some(queue) = GLOBAL_BLOCKED.lock().unwrap().get(key) {
+        for parked in queue.iter() {
+            parked.notify.notify_one();
+        }
+    }
+}
+
+// Wake the blocking command of a client that disconnected, so it notices
+// right away instead of at its next poll
+pub fn wake_client(cid: u64) {
+    let blocked = GLOBAL_BLOCKED.lock().unwrap();
+    for parked in blocked.values().flatten().filter(|p| p.cid == cid) {
+        parked.notify.notify_one();
+    }
+}
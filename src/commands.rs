@@ -1,12 +1,44 @@
 use redis_module::{Context, NextArg, RedisError, RedisResult, RedisValue, RedisString, ThreadSafeContext };
-use crate::utils::{ redis_resp, tokio_spawn };
+use crate::utils::{ client_id, from_hex, now_millis, redis_resp, tokio_spawn, tokio_spawn_for };
 use crate::tikv::*;
+use crate::pd::do_async_count_approx;
+use crate::numeric::{ parse_float, parse_score, do_async_incr_by_float };
+use crate::hash::*;
+use crate::list::*;
+use crate::set::*;
+use crate::zset::*;
+use crate::sort::{ SortOptions, do_async_sort };
+use crate::history::*;
+use crate::blocking::start_waiting;
+use crate::config::{ check_allowed, check_writable };
+use crate::retry::{ with_retry, RetryClass };
+use crate::encoding::DataType;
 use tikv_client::{KvPair};
 
+// Keys starting with a zero byte hold the module's own bookkeeping, see
+// encoding.rs, so they are refused as user keys
+fn check_key(key: &str) -> Result<(), RedisError> {
+    if key.as_bytes().first() == Some(&0) {
+        return Err(RedisError::Str("ERR keys starting with a zero byte are reserved"));
+    }
+    Ok(())
+}
+
+fn check_keys(keys: &[String]) -> Result<(), RedisError> {
+    keys.iter().try_for_each(|key| check_key(key))
+}
+
+fn next_key<'a, I: NextArg>(args: &mut I) -> Result<&'a str, RedisError> {
+    let key = args.next_str()?;
+    check_key(key)?;
+    Ok(key)
+}
+
 pub fn tikv_connect(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
-    if args.len() < 1 {
+    if args.is_empty() {
         return Err(RedisError::WrongArity);
     }
+    check_allowed(ctx, &args)?;
     let mut addrs: Vec<String> = Vec::new();
     if args.len() == 1 {
         addrs.push(String::from("127.0.0.1:2379"));
@@ -22,7 +54,25 @@ pub fn tikv_connect(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
     Ok(RedisValue::NoReply)
 }
 
-pub fn tikv_close(ctx: &Context, _args: Vec<RedisString>) -> RedisResult {
+pub fn tikv_switch_cluster(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_allowed(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let timeout_ms = args.next_u64()?;
+    let addrs: Vec<String> = args.map(|s| s.to_string()).collect();
+
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = do_async_switch_cluster(addrs, timeout_ms).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_close(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_allowed(ctx, &args)?;
     let blocked_client = ctx.block_client();
     tokio_spawn(async move {
         let res = do_async_close().await;
@@ -36,57 +86,369 @@ pub fn tikv_get(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
         return Err(RedisError::WrongArity);
     }
     let mut args = args.into_iter().skip(1);
-    let key = args.next_str()?;
+    let key = next_key(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
     let blocked_client = ctx.block_client();
-    tokio_spawn(async move {
-        let res = do_async_get(key).await;
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_get(key, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_strlen(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_strlen(key, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_getrange(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let start = args.next_i64()?;
+    let end = args.next_i64()?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_getrange(key, start, end, backend)).await;
         redis_resp(blocked_client, res);
     });
     Ok(RedisValue::NoReply)
 }
 
+// Absolute expiration in Unix milliseconds for an EX/PX/EXAT/PXAT argument
+fn parse_expire_time(unit: &str, value: &str, command: &str) -> Result<u64, RedisError> {
+    let value: i64 = value.parse().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    if value <= 0 {
+        return Err(RedisError::String(format!("ERR invalid expire time in '{}' command", command)));
+    }
+    let value = value as u64;
+    Ok(match unit {
+        "EX" => now_millis().saturating_add(value.saturating_mul(1000)),
+        "PX" => now_millis().saturating_add(value),
+        "EXAT" => value.saturating_mul(1000),
+        _ => value,
+    })
+}
+
+// SET options: NX|XX and EX seconds|PX milliseconds|EXAT unix-seconds|
+// PXAT unix-milliseconds|KEEPTTL
+fn parse_put_options<I: Iterator<Item = RedisString>>(args: &mut I) -> Result<PutOptions, RedisError> {
+    let mut opts = PutOptions::default();
+    let mut has_condition = false;
+    let mut has_expiry = false;
+    while let Ok(opt) = args.next_str() {
+        let opt = opt.to_uppercase();
+        match opt.as_str() {
+            "NX" | "XX" if !has_condition => {
+                opts.condition = if opt == "NX" { PutCondition::NotExists } else { PutCondition::Exists };
+                has_condition = true;
+            },
+            "KEEPTTL" if !has_expiry => {
+                opts.expiry = Expiry::Keep;
+                has_expiry = true;
+            },
+            "EX" | "PX" | "EXAT" | "PXAT" if !has_expiry => {
+                let value = args.next_str().map_err(|_| RedisError::Str("ERR syntax error"))?;
+                opts.expiry = Expiry::At(parse_expire_time(&opt, value, "set")?);
+                has_expiry = true;
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    Ok(opts)
+}
+
 pub fn tikv_put(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
     if args.len() < 3 {
         return Err(RedisError::WrongArity);
     }
     let mut args = args.into_iter().skip(1);
-    let key = args.next_str()?;
+    let key = next_key(&mut args)?;
+    let value = args.next_str()?;
+    let opts = parse_put_options(&mut args)?;
+    // With NX or XX the reply tells whether the key existed, which a replay
+    // of an applied write would get wrong
+    let class = if opts.condition == PutCondition::Always { RetryClass::Write } else { RetryClass::NonIdempotent };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(class, || do_async_put(key, value, opts)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_setnx(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let value = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_setnx(key, value)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.setex key seconds value and tikv.psetex key milliseconds value
+pub fn tikv_setex(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let command = args[0].to_string().to_lowercase();
+    let unit = if command.ends_with("psetex") { "PX" } else { "EX" };
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let expire_at = parse_expire_time(unit, args.next_str()?, command.trim_start_matches("tikv."))?;
+    let value = args.next_str()?;
+    let opts = PutOptions { expiry: Expiry::At(expire_at), ..PutOptions::default() };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::Write, || do_async_put(key, value, opts)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_getset(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let value = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_getset(key, value)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.getex key [EX seconds|PX milliseconds|EXAT unix-seconds|
+// PXAT unix-milliseconds|PERSIST]
+pub fn tikv_getex(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(2..=4).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    // Without option GETEX is a plain read
+    if args.len() > 2 {
+        check_writable(ctx, &args)?;
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let expiry = match args.next_str() {
+        Ok(opt) => match opt.to_uppercase().as_str() {
+            "PERSIST" if args.len() == 0 => Expiry::Persist,
+            unit @ ("EX" | "PX" | "EXAT" | "PXAT") if args.len() == 1 => {
+                Expiry::At(parse_expire_time(unit, args.next_str()?, "getex")?)
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        },
+        Err(_) => Expiry::Keep,
+    };
+    let class = if expiry == Expiry::Keep { RetryClass::Read } else { RetryClass::Write };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(class, || do_async_getex(key, expiry)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_append(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let value = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_append(key, value)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.incr key and tikv.decr key
+pub fn tikv_incr(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let delta = if args[0].to_string().to_lowercase().ends_with("decr") { -1 } else { 1 };
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_incr_by(key, delta)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.incrby key delta and tikv.decrby key delta
+pub fn tikv_incr_by(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let decr = args[0].to_string().to_lowercase().ends_with("decrby");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let delta = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let delta = if decr {
+        delta.checked_neg().ok_or(RedisError::Str("ERR decrement would overflow"))?
+    } else {
+        delta
+    };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_incr_by(key, delta)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_incr_by_float(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let delta = parse_float(args.next_str()?.as_bytes()).ok_or(RedisError::Str("ERR value is not a valid float"))?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_incr_by_float(key, delta)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// Largest value SETRANGE may produce, same limit as Redis' proto-max-bulk-len
+const MAX_STRING_LEN: u64 = 512 * 1024 * 1024;
+
+pub fn tikv_setrange(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let offset = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let value = args.next_str()?;
+    if offset < 0 || offset as u64 + value.len() as u64 > MAX_STRING_LEN {
+        return Err(RedisError::Str("ERR offset is out of range"));
+    }
+    let offset = offset as usize;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        // Writing nothing leaves a missing key missing, just report the length
+        let res = if value.is_empty() {
+            with_retry(RetryClass::Read, || do_async_strlen(key, DEFAULT_READ_BACKEND)).await
+        } else {
+            with_retry(RetryClass::Write, || do_async_setrange(key, offset, value)).await
+        };
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_cas(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let expected = args.next_str()?;
     let value = args.next_str()?;
     let blocked_client = ctx.block_client();
     tokio_spawn(async move {
-        let res = do_async_put(key, value).await;
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_cas(key, expected, value)).await;
         redis_resp(blocked_client, res);
     });
     Ok(RedisValue::NoReply)
 }
 
 pub fn tikv_del(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
     if args.len() < 2 {
         return Err(RedisError::WrongArity);
     }
     let keys: Vec<String> = args.into_iter().skip(1).map(|s| s.to_string()).collect();
+    check_keys(&keys)?;
     let blocked_client = ctx.block_client();
     tokio_spawn(async move {
-        let res = do_async_batch_del(keys).await;
+        let res = with_retry(RetryClass::Write, || do_async_batch_del(keys.clone())).await;
         redis_resp(blocked_client, res);
     });
     Ok(RedisValue::NoReply)
 
 }
 
+pub fn tikv_restore_key(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if !(2..=3).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let replace = if num_args == 3 {
+        match args.next_str()?.to_uppercase().as_str() {
+            "REPLACE" => true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    } else {
+        false
+    };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_restore_key(key, replace)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
 pub fn tikv_load(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
     if args.len() < 2 {
         return Err(RedisError::WrongArity);
     }
     let mut args = args.into_iter().skip(1);
-    let key = args.next_str()?;
+    let key = next_key(&mut args)?;
     let blocked_client = ctx.block_client();
-    tokio_spawn(async move {
+    tokio_spawn_for(client_id(ctx), async move {
         let tctx = ThreadSafeContext::with_blocked_client(blocked_client);
-        let res = do_async_get_raw(key).await;
+        let res = with_retry(RetryClass::Read, || do_async_get_raw(key)).await;
         match res {
             Ok(data) => {
-                if data.len() > 0 {
+                if !data.is_empty() {
                     let data_str = std::str::from_utf8(&data);
                     tctx.lock().call("SET", &[key, data_str.unwrap()]).unwrap();
                 }
@@ -115,14 +477,154 @@ pub fn tikv_scan(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
         end_key = "";
     }
     let limit = args.next_u64()?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
 
     let blocked_client = ctx.block_client();
-    tokio_spawn(async move {
+    tokio_spawn_for(cid, async move {
         if num_args == 3 {
-            let res = do_async_scan(start_key, limit).await;
+            let res = with_retry(RetryClass::Read, || do_async_scan(start_key, limit, backend)).await;
+            redis_resp(blocked_client, res);
+        } else {
+            let res = with_retry(RetryClass::Read, || do_async_scan_range(start_key, end_key, limit, backend)).await;
+            redis_resp(blocked_client, res);
+        }
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.scankeys cursor [MATCH pattern] [COUNT count] [TYPE type] [SNAPSHOT]
+pub fn tikv_scan_keys(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    // A cursor of a pinned iteration is the snapshot id, ':' and the hex
+    // position
+    let cursor_arg = args.next_str()?;
+    let (mut pin, cursor) = match cursor_arg.split_once(':') {
+        Some((id, position)) => {
+            let id = id.parse::<u64>().map_err(|_| RedisError::Str("ERR invalid cursor"))?;
+            (ScanSnapshot::Pinned(id), parse_scan_cursor(position)?)
+        },
+        None => (ScanSnapshot::Fresh, parse_scan_cursor(cursor_arg)?),
+    };
+    let mut pattern = None;
+    let mut count: u32 = 10;
+    let mut filter = None;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "MATCH" => pattern = Some(args.next_arg()?.as_slice().to_vec()),
+            "COUNT" => count = match args.next_i64() {
+                Ok(value) if value > 0 => value.min(u32::MAX as i64) as u32,
+                _ => return Err(RedisError::Str("ERR value is out of range, must be positive")),
+            },
+            "TYPE" => filter = match DataType::parse(args.next_str()?) {
+                Some(data_type) => Some(data_type),
+                None => return Err(RedisError::Str("ERR unknown type name")),
+            },
+            "SNAPSHOT" if pin == ScanSnapshot::Fresh && cursor.is_none() => pin = ScanSnapshot::Pin,
+            "SNAPSHOT" => return Err(RedisError::Str("ERR SNAPSHOT starts a new iteration, use it with cursor 0")),
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_scan_keys(cursor.clone(), pattern.clone(), filter, count, pin, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.ttlscan cursor min-ttl max-ttl [COUNT count], TTLs in milliseconds
+pub fn tikv_ttl_scan(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 4 && args.len() != 6 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let cursor = parse_scan_cursor(args.next_str()?)?;
+    let min_ttl = args.next_u64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let max_ttl = args.next_u64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let (_, count) = parse_scan_options(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_ttl_scan(cursor.clone(), min_ttl, max_ttl, count, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_scan_values(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let start_key = args.next_str()?;
+    let end_key = if num_args > 3 { Some(args.next_str()?) } else { None };
+    let limit = args.next_u64()?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_scan_values(start_key, end_key, limit, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_batch_scan(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 4 || args.len() % 2 != 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let num_ranges = (args.len() - 2) / 2;
+    let mut args = args.into_iter().skip(1);
+    let each_limit = args.next_u64()?;
+    let mut ranges: Vec<(String, String)> = Vec::new();
+    for _i in 0..num_ranges {
+        let start_key = args.next_string()?;
+        let end_key = args.next_string()?;
+        ranges.push((start_key, end_key));
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_batch_scan(ranges.clone(), each_limit, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_count(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(2..=3).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let prefix = args.next_str()?;
+    let approx = if num_args == 3 {
+        match args.next_str()?.to_uppercase().as_str() {
+            "APPROX" => true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    } else {
+        false
+    };
+
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(client_id(ctx), async move {
+        if approx {
+            let res = do_async_count_approx(prefix).await;
             redis_resp(blocked_client, res);
         } else {
-            let res = do_async_scan_range(start_key, end_key, limit).await;
+            let res = with_retry(RetryClass::Read, || do_async_count(prefix)).await;
             redis_resp(blocked_client, res);
         }
     });
@@ -130,6 +632,7 @@ pub fn tikv_scan(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
 }
 
 pub fn tikv_del_range(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
     if args.len() < 3 {
         return Err(RedisError::WrongArity);
     }
@@ -138,7 +641,24 @@ pub fn tikv_del_range(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
     let key_end = args.next_str()?;
     let blocked_client = ctx.block_client();
     tokio_spawn(async move {
-        let res = do_async_delete_range(key_start, key_end).await;
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_delete_range(key_start, key_end)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.migrateraw [startkey] [endkey]
+pub fn tikv_migrate_raw(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() > 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key_start = args.next_str().unwrap_or("");
+    let key_end = args.next_str().ok();
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_migrate_raw(key_start, key_end)).await;
         redis_resp(blocked_client, res);
     });
     Ok(RedisValue::NoReply)
@@ -150,30 +670,1533 @@ pub fn tikv_batch_get(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
     }
 
     let keys: Vec<String> = args.into_iter().skip(1).map(|s| s.to_string()).collect();
+    check_keys(&keys)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
     let blocked_client = ctx.block_client();
-    tokio_spawn(async move {
-        let res = do_async_batch_get(keys).await;
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_batch_get(keys.clone(), backend)).await;
         redis_resp(blocked_client, res);
     });
     Ok(RedisValue::NoReply)
 }
 
-pub fn tikv_batch_put(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+pub fn tikv_mget_any(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 2 {
+        return Err(RedisError::WrongArity);
+    }
+
+    let keys: Vec<String> = args.into_iter().skip(1).map(|s| s.to_string()).collect();
+    check_keys(&keys)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_mget_any(keys.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_batch_put(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    let command = args[0].to_string().to_lowercase();
     let num_kvs = args.len() - 1;
-    if num_kvs % 2 != 0 {
+    if num_kvs == 0 || num_kvs % 2 != 0 {
         return Err(RedisError::WrongArity);
     }
     let mut kvs: Vec<KvPair> = Vec::new();
     let mut args = args.into_iter().skip(1);
     for _i in 0..num_kvs/2 {
-        let key = args.next_str()?;
+        let key = next_key(&mut args)?;
         let value = args.next_str()?;
         let kv = KvPair::from((key.to_owned(), value.to_owned()));
         kvs.push(kv);
     }
+    let nx = command.ends_with("msetnx");
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = if nx {
+            with_retry(RetryClass::NonIdempotent, || do_async_batch_put_nx(kvs.clone())).await
+        } else {
+            with_retry(RetryClass::Write, || do_async_batch_put(kvs.clone())).await
+        };
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_bitpos(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(3..=6).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let bit = match args.next_i64()? {
+        0 => 0,
+        1 => 1,
+        _ => return Err(RedisError::Str("ERR The bit argument must be 1 or 0.")),
+    };
+    let start = if num_args > 3 { Some(args.next_i64()?) } else { None };
+    let end = if num_args > 4 { Some(args.next_i64()?) } else { None };
+    let bit_unit = if num_args > 5 {
+        match args.next_str()?.to_uppercase().as_str() {
+            "BIT" => true,
+            "BYTE" => false,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    } else {
+        false
+    };
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_bitpos(key, bit, start, end, bit_unit, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_lcs(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key1 = next_key(&mut args)?;
+    let key2 = next_key(&mut args)?;
+    let mut want_len = false;
+    let mut want_idx = false;
+    let mut min_match_len: u64 = 0;
+    let mut with_match_len = false;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "LEN" => want_len = true,
+            "IDX" => want_idx = true,
+            "MINMATCHLEN" => min_match_len = args.next_u64()?,
+            "WITHMATCHLEN" => with_match_len = true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    if want_len && want_idx {
+        return Err(RedisError::Str("ERR If you want both the length and indexes, please just use IDX."));
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_lcs(key1, key2, want_len, want_idx, min_match_len, with_match_len, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_read_mode(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() > 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let cid = client_id(ctx);
+    if args.len() == 2 {
+        let mut args = args.into_iter().skip(1);
+        let mode = args.next_str()?;
+        // Nothing writes the raw keyspace anymore, reading it would only
+        // ever find stale data and ignore types and expiration
+        if mode.eq_ignore_ascii_case("RAW") {
+            return Err(RedisError::Str("ERR RAW read mode was removed, copy raw data with tikv.migrateraw"));
+        }
+        let backend = match ReadBackend::parse(mode) {
+            Some(backend) => backend,
+            None => return Err(RedisError::Str("ERR read mode must be TXN")),
+        };
+        set_read_backend(cid, backend);
+    }
+    Ok(RedisValue::Array(vec![
+        "mode".into(),
+        get_read_backend(cid).name().into(),
+        "default".into(),
+        DEFAULT_READ_BACKEND.name().into(),
+    ]))
+}
+
+pub fn tikv_read_snapshot(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let cid = client_id(ctx);
+    let mut args = args.into_iter().skip(1);
+    match args.next_str()?.to_uppercase().as_str() {
+        "ON" => {
+            let blocked_client = ctx.block_client();
+            tokio_spawn_for(cid, async move {
+                let res = do_async_pin_snapshot(cid).await;
+                redis_resp(blocked_client, res);
+            });
+            Ok(RedisValue::NoReply)
+        },
+        "OFF" => {
+            unpin_snapshot(cid);
+            Ok("OK".into())
+        },
+        _ => Err(RedisError::Str("ERR syntax error, use ON or OFF")),
+    }
+}
+
+pub fn tikv_pool(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_allowed(ctx, &args)?;
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    match args.next_str()?.to_uppercase().as_str() {
+        "STATUS" => Ok(pool_status()),
+        "FLUSH" => {
+            let blocked_client = ctx.block_client();
+            tokio_spawn(async move {
+                let res = do_async_pool_flush().await;
+                redis_resp(blocked_client, res);
+            });
+            Ok(RedisValue::NoReply)
+        },
+        _ => Err(RedisError::Str("ERR unknown subcommand, use STATUS or FLUSH")),
+    }
+}
+
+pub fn tikv_wait_key(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let timeout_ms = args.next_u64()?;
+    let cid = client_id(ctx);
+    start_waiting(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = do_async_wait_key(cid, key, timeout_ms).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.flashback key unix-ms | tikv.flashback PREFIX prefix unix-ms
+pub fn tikv_flashback(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    let prefix = match args.len() {
+        3 => false,
+        4 if args[1].to_string().eq_ignore_ascii_case("PREFIX") => true,
+        4 => return Err(RedisError::Str("ERR syntax error")),
+        _ => return Err(RedisError::WrongArity),
+    };
+    let mut args = args.into_iter().skip(if prefix { 2 } else { 1 });
+    let key = next_key(&mut args)?;
+    if prefix && key.is_empty() {
+        return Err(RedisError::Str("ERR PREFIX must not be empty"));
+    }
+    let ms = args.next_u64().map_err(|_| RedisError::Str("ERR timestamp is not an integer or out of range"))?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::Write, || do_async_flashback(key, prefix, ms)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.history key [WINDOW seconds] [SAMPLES samples]
+pub fn tikv_history(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(2..=6).contains(&args.len()) || args.len() % 2 != 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let mut window_ms = HISTORY_WINDOW_MS;
+    let mut samples = HISTORY_SAMPLES;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "WINDOW" => window_ms = match args.next_u64() {
+                Ok(secs) if secs > 0 => secs.saturating_mul(1000),
+                _ => return Err(RedisError::Str("ERR WINDOW must be a positive number of seconds")),
+            },
+            "SAMPLES" => samples = match args.next_u64() {
+                Ok(n) if (1..=HISTORY_MAX_SAMPLES).contains(&n) => n,
+                _ => return Err(RedisError::String(format!("ERR SAMPLES must be between 1 and {}", HISTORY_MAX_SAMPLES))),
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    let cid = client_id(ctx);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_history(key, window_ms, samples)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hset(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 4 || args.len() % 2 != 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    while let Ok(field) = args.next_string() {
+        pairs.push((field, args.next_string()?));
+    }
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hset(key, pairs.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hget(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let field = args.next_str()?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hget(key, field, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hdel(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let fields: Vec<String> = args.map(|s| s.to_string()).collect();
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hdel(key, fields.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.hbatchdel key field [key field ...]
+pub fn tikv_hbatchdel(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 || args.len() % 2 == 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    while let Ok(key) = args.next_string() {
+        check_key(&key)?;
+        pairs.push((key, args.next_string()?));
+    }
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hbatchdel(pairs.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hlen(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hlen(key, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hmget(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let fields: Vec<String> = args.map(|s| s.to_string()).collect();
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hmget(key, fields.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.hmgetmulti key numfields field [field ...] [key numfields field ...]
+pub fn tikv_hmget_multi(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let mut requests: Vec<(String, Vec<String>)> = Vec::new();
+    while let Ok(key) = args.next_string() {
+        check_key(&key)?;
+        let count = args.next_u64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+        if count == 0 {
+            return Err(RedisError::Str("ERR numfields must be positive"));
+        }
+        let mut fields = Vec::new();
+        for _ in 0..count {
+            fields.push(args.next_string().map_err(|_| RedisError::Str("ERR syntax error"))?);
+        }
+        requests.push((key, fields));
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hmget_multi(requests.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hincr_by(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let field = args.next_str()?;
+    let delta = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hincr_by(key, field, delta)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hincr_by_float(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let field = args.next_str()?;
+    let delta = parse_float(args.next_str()?.as_bytes()).ok_or(RedisError::Str("ERR value is not a valid float"))?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hincr_by_float(key, field, delta)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hsetnx(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let field = args.next_str()?;
+    let value = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hsetnx(key, field, value)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hrandfield(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(2..=4).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let count = if num_args > 2 {
+        Some(args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?)
+    } else {
+        None
+    };
+    let with_values = if num_args > 3 {
+        match args.next_str()?.to_uppercase().as_str() {
+            "WITHVALUES" => true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    } else {
+        false
+    };
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hrandfield(key, count, with_values, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.hgetall key [CURSOR cursor]
+pub fn tikv_hgetall(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 && args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cursor = parse_page_cursor(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hgetall(key, cursor.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_hstrlen(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let field = args.next_str()?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_hstrlen(key, field, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// FIELDS numfields field [field ...], the tail of the hash field TTL commands
+fn parse_fields<I: Iterator<Item = RedisString>>(args: &mut I) -> Result<Vec<String>, RedisError> {
+    if !args.next_str()?.eq_ignore_ascii_case("FIELDS") {
+        return Err(RedisError::Str("ERR syntax error"));
+    }
+    let count = args.next_u64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let fields: Vec<String> = args.map(|s| s.to_string()).collect();
+    if count == 0 || fields.len() as u64 != count {
+        return Err(RedisError::Str("ERR The `numfields` parameter must match the number of arguments"));
+    }
+    Ok(fields)
+}
+
+// tikv.hexpire key seconds [NX|XX|GT|LT] FIELDS numfields field [field ...]
+// and tikv.hpexpire, the same in milliseconds
+pub fn tikv_hexpire(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 6 {
+        return Err(RedisError::WrongArity);
+    }
+    let command = args[0].to_string().to_lowercase();
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = next_key(&mut args)?;
+    let value = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    if value < 0 {
+        return Err(RedisError::String(format!("ERR invalid expire time in '{}' command", command)));
+    }
+    let value = value as u64;
+    let expire_at = if command.ends_with("hpexpire") {
+        now_millis().saturating_add(value)
+    } else {
+        now_millis().saturating_add(value.saturating_mul(1000))
+    };
+    let condition = match args.peek().map(|s| s.to_string().to_uppercase()).as_deref() {
+        Some("NX") => ExpireCondition::NotSet,
+        Some("XX") => ExpireCondition::IsSet,
+        Some("GT") => ExpireCondition::Greater,
+        Some("LT") => ExpireCondition::Less,
+        _ => ExpireCondition::Always,
+    };
+    if condition != ExpireCondition::Always {
+        args.next();
+    }
+    let fields = parse_fields(&mut args)?;
     let blocked_client = ctx.block_client();
     tokio_spawn(async move {
-        let res = do_async_batch_put(kvs).await;
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_hexpire(key, expire_at, condition, fields.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.httl key FIELDS numfields field [field ...]
+pub fn tikv_httl(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 5 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let fields = parse_fields(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_httl(key, fields.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.lpush and tikv.rpush key element [element ...]
+pub fn tikv_push(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let left = args[0].to_string().to_lowercase().ends_with("lpush");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let values: Vec<String> = args.map(|s| s.to_string()).collect();
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_push(key, values.clone(), left)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.lpop and tikv.rpop key [count]
+pub fn tikv_pop(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if !(2..=3).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let left = args[0].to_string().to_lowercase().ends_with("lpop");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let count = match args.next_str() {
+        Ok(value) => match value.parse::<i64>() {
+            Ok(count) if count >= 0 => Some(count as u64),
+            _ => return Err(RedisError::Str("ERR value is out of range, must be positive")),
+        },
+        Err(_) => None,
+    };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_pop(key, count, left)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_lrange(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let start = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let stop = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_lrange(key, start, stop, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_llen(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_llen(key, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_lindex(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let index = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_lindex(key, index, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_lset(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let index = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let value = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::Write, || do_async_lset(key, index, value)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_ltrim(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let start = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let stop = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_ltrim(key, start, stop)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.linsert key BEFORE|AFTER pivot element
+pub fn tikv_linsert(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 5 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let before = match args.next_str()?.to_uppercase().as_str() {
+        "BEFORE" => true,
+        "AFTER" => false,
+        _ => return Err(RedisError::Str("ERR syntax error")),
+    };
+    let pivot = args.next_str()?;
+    let value = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_linsert(key, before, pivot, value)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+fn parse_list_end(value: &str) -> Result<bool, RedisError> {
+    match value.to_uppercase().as_str() {
+        "LEFT" => Ok(true),
+        "RIGHT" => Ok(false),
+        _ => Err(RedisError::Str("ERR syntax error")),
+    }
+}
+
+// tikv.lmove source destination LEFT|RIGHT LEFT|RIGHT, and
+// tikv.rpoplpush source destination, the same as RIGHT LEFT
+pub fn tikv_lmove(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    let rpoplpush = args[0].to_string().to_lowercase().ends_with("rpoplpush");
+    if (rpoplpush && args.len() != 3) || (!rpoplpush && args.len() != 5) {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let source = next_key(&mut args)?;
+    let destination = next_key(&mut args)?;
+    let (from_left, to_left) = if rpoplpush {
+        (false, true)
+    } else {
+        (parse_list_end(args.next_str()?)?, parse_list_end(args.next_str()?)?)
+    };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_lmove(source, destination, from_left, to_left)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.blpop and tikv.brpop key [key ...] timeout, the timeout in seconds
+pub fn tikv_blocking_pop(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let left = args[0].to_string().to_lowercase().ends_with("blpop");
+    let timeout = match args[args.len() - 1].to_string().parse::<f64>() {
+        Ok(timeout) if timeout.is_finite() && timeout < 0.0 => return Err(RedisError::Str("ERR timeout is negative")),
+        Ok(timeout) if timeout.is_finite() => timeout,
+        _ => return Err(RedisError::Str("ERR timeout is not a float or out of range")),
+    };
+    // Same limit as Redis, milliseconds must fit a signed 64-bit integer
+    if timeout * 1000.0 >= i64::MAX as f64 {
+        return Err(RedisError::Str("ERR timeout is out of range"));
+    }
+    let timeout_ms = (timeout * 1000.0) as u64;
+    let keys: Vec<String> = args[1..args.len() - 1].iter().map(|s| s.to_string()).collect();
+    check_keys(&keys)?;
+    let cid = client_id(ctx);
+    start_waiting(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = do_async_blocking_pop(cid, keys, left, timeout_ms).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.lpos key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+pub fn tikv_lpos(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 || args.len() % 2 == 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let element = args.next_str()?;
+    let mut opts = PosOptions { rank: 1, count: None, maxlen: 0 };
+    while let Ok(opt) = args.next_str() {
+        let value = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+        match opt.to_uppercase().as_str() {
+            "RANK" if value == 0 || value == i64::MIN => return Err(RedisError::Str("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list")),
+            "RANK" => opts.rank = value,
+            "COUNT" if value < 0 => return Err(RedisError::Str("ERR COUNT can't be negative")),
+            "COUNT" => opts.count = Some(value as u64),
+            "MAXLEN" if value < 0 => return Err(RedisError::Str("ERR MAXLEN can't be negative")),
+            "MAXLEN" => opts.maxlen = value as u64,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_lpos(key, element, opts, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_sadd(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let members: Vec<String> = args.map(|s| s.to_string()).collect();
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_sadd(key, members.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_srem(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let members: Vec<String> = args.map(|s| s.to_string()).collect();
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_srem(key, members.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.smembers key [CURSOR cursor]
+pub fn tikv_smembers(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 && args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cursor = parse_page_cursor(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_smembers(key, cursor.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_scard(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_scard(key, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.sismember key member and tikv.smismember key member [member ...]
+pub fn tikv_sismember(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    let multi = args[0].to_string().to_lowercase().ends_with("smismember");
+    if args.len() < 3 || (!multi && args.len() != 3) {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let members: Vec<String> = args.map(|s| s.to_string()).collect();
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_sismember(key, members.clone(), multi, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_srandmember(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(2..=3).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let count = if num_args > 2 {
+        Some(args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?)
+    } else {
+        None
+    };
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_srandmember(key, count, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_spop(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if !(2..=3).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let count = match args.next_str() {
+        Ok(value) => match value.parse::<i64>() {
+            Ok(count) if count >= 0 => Some(count as u64),
+            _ => return Err(RedisError::Str("ERR value is out of range, must be positive")),
+        },
+        Err(_) => None,
+    };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_spop(key, count)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+fn parse_set_op(command: &str) -> SetOp {
+    if command.contains("sinter") {
+        SetOp::Inter
+    } else if command.contains("sunion") {
+        SetOp::Union
+    } else {
+        SetOp::Diff
+    }
+}
+
+// tikv.sinter, tikv.sunion and tikv.sdiff key [key ...]
+pub fn tikv_set_algebra(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let op = parse_set_op(&args[0].to_string().to_lowercase());
+    let keys: Vec<String> = args.into_iter().skip(1).map(|s| s.to_string()).collect();
+    check_keys(&keys)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_set_algebra(op, keys.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.sinterstore, tikv.sunionstore and tikv.sdiffstore destination key [key ...]
+pub fn tikv_set_algebra_store(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let op = parse_set_op(&args[0].to_string().to_lowercase());
+    let mut args = args.into_iter().skip(1);
+    let destination = next_key(&mut args)?;
+    let keys: Vec<String> = args.map(|s| s.to_string()).collect();
+    check_keys(&keys)?;
+    // Storing into one of the sources changes what a replay reads
+    let class = if keys.iter().any(|k| k == destination) { RetryClass::NonIdempotent } else { RetryClass::Write };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(class, || do_async_set_algebra_store(op, destination, keys.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.sintercard numkeys key [key ...] [LIMIT limit]
+pub fn tikv_sintercard(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let numkeys = match args.next_i64() {
+        Ok(n) if n > 0 => n as usize,
+        _ => return Err(RedisError::Str("ERR numkeys should be greater than 0")),
+    };
+    let keys: Vec<String> = args.by_ref().take(numkeys).map(|s| s.to_string()).collect();
+    if keys.len() != numkeys {
+        return Err(RedisError::Str("ERR Number of keys can't be greater than number of args"));
+    }
+    check_keys(&keys)?;
+    let mut limit = 0;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "LIMIT" => limit = match args.next_i64() {
+                Ok(value) if value >= 0 => value as u64,
+                _ => return Err(RedisError::Str("ERR LIMIT can't be negative")),
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_sintercard(keys.clone(), limit, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_smove(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let source = next_key(&mut args)?;
+    let destination = next_key(&mut args)?;
+    let member = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_smove(source, destination, member)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// Collection scan cursor: "0" to start, otherwise the hex string the
+// previous call replied, holding the member to continue from
+fn parse_scan_cursor(value: &str) -> Result<Option<Vec<u8>>, RedisError> {
+    if value == "0" {
+        return Ok(None);
+    }
+    from_hex(value).map(Some).ok_or(RedisError::Str("ERR invalid cursor"))
+}
+
+// Trailing [CURSOR cursor] of the commands paged by max-reply-size, a
+// cursor of "0" starting from the beginning like none
+fn parse_page_cursor<I: Iterator<Item = RedisString>>(args: &mut I) -> Result<Option<Vec<u8>>, RedisError> {
+    match args.next() {
+        None => Ok(None),
+        Some(opt) if opt.to_string().eq_ignore_ascii_case("CURSOR") => {
+            let cursor = args.next_str()?;
+            if cursor == "0" {
+                return Ok(Some(Vec::new()));
+            }
+            from_hex(cursor).map(Some).ok_or(RedisError::Str("ERR invalid cursor"))
+        },
+        Some(_) => Err(RedisError::Str("ERR syntax error")),
+    }
+}
+
+// Trailing [MATCH pattern] [COUNT count] of the collection scans, COUNT
+// defaulting to 10
+fn parse_scan_options<I: Iterator<Item = RedisString>>(args: &mut I) -> Result<(Option<Vec<u8>>, u32), RedisError> {
+    let mut pattern = None;
+    let mut count: u32 = 10;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "MATCH" => pattern = Some(args.next_arg()?.as_slice().to_vec()),
+            "COUNT" => count = match args.next_i64() {
+                Ok(value) if value > 0 => value.min(u32::MAX as i64) as u32,
+                _ => return Err(RedisError::Str("ERR value is out of range, must be positive")),
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    Ok((pattern, count))
+}
+
+// tikv.sscan key cursor [MATCH pattern] [COUNT count]
+pub fn tikv_sscan(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 || args.len() % 2 == 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cursor = parse_scan_cursor(args.next_str()?)?;
+    let (pattern, count) = parse_scan_options(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_sscan(key, cursor.clone(), pattern.clone(), count, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.sort key [BY pattern] [LIMIT offset count] [GET pattern ...] [ASC|DESC] [ALPHA]
+pub fn tikv_sort(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let mut opts = SortOptions { by: None, limit: None, gets: Vec::new(), desc: false, alpha: false };
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "BY" => opts.by = Some(args.next_str()?.to_string()),
+            "LIMIT" => {
+                let offset = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+                let count = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+                opts.limit = Some((offset, count));
+            },
+            "GET" => opts.gets.push(args.next_str()?.to_string()),
+            "ASC" => opts.desc = false,
+            "DESC" => opts.desc = true,
+            "ALPHA" => opts.alpha = true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_sort(key, opts.clone(), backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zadd key [NX|XX] [GT|LT] [CH] score member [score member ...]
+pub fn tikv_zadd(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = next_key(&mut args)?;
+    let mut opts = ZAddOptions::default();
+    while let Some(flag) = args.peek().map(|s| s.to_string().to_uppercase()) {
+        match flag.as_str() {
+            "NX" => opts.nx = true,
+            "XX" => opts.xx = true,
+            "GT" => opts.gt = true,
+            "LT" => opts.lt = true,
+            "CH" => opts.ch = true,
+            _ => break,
+        }
+        args.next();
+    }
+    if opts.nx && opts.xx {
+        return Err(RedisError::Str("ERR XX and NX options at the same time are not compatible"));
+    }
+    if (opts.gt && opts.lt) || (opts.nx && (opts.gt || opts.lt)) {
+        return Err(RedisError::Str("ERR GT, LT, and/or NX options at the same time are not compatible"));
+    }
+    let rest: Vec<RedisString> = args.collect();
+    if rest.is_empty() || rest.len() % 2 != 0 {
+        return Err(RedisError::Str("ERR syntax error"));
+    }
+    let mut pairs: Vec<(f64, String)> = Vec::new();
+    for pair in rest.chunks(2) {
+        let score = parse_score(pair[0].as_slice()).ok_or(RedisError::Str("ERR value is not a valid float"))?;
+        pairs.push((score, pair[1].to_string()));
+    }
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_zadd(key, opts, pairs.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_zscore(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let member = args.next_str()?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zscore(key, member, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zcard key
+pub fn tikv_zcard(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 2 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zcard(key, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zrange and tikv.zrevrange key start stop [WITHSCORES]
+pub fn tikv_zrange(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if !(4..=5).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let rev = args[0].to_string().to_lowercase().ends_with("zrevrange");
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let start = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let stop = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+    let with_scores = if num_args > 4 {
+        match args.next_str()?.to_uppercase().as_str() {
+            "WITHSCORES" => true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    } else {
+        false
+    };
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zrange(key, start, stop, rev, with_scores, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// Score interval end: a float, "(" before it for an exclusive bound, or
+// -inf / +inf
+fn parse_score_bound(value: &str) -> Result<ScoreBound, RedisError> {
+    let (value, inclusive) = match value.strip_prefix('(') {
+        Some(rest) => (rest, false),
+        None => (value, true),
+    };
+    let score = parse_score(value.as_bytes()).ok_or(RedisError::Str("ERR min or max is not a float"))?;
+    Ok(ScoreBound { score, inclusive })
+}
+
+// Trailing [WITHSCORES] [LIMIT offset count] of the range commands
+fn parse_range_options<I: Iterator<Item = RedisString>>(args: &mut I) -> Result<(bool, Limit), RedisError> {
+    let mut with_scores = false;
+    let mut limit = Limit::ALL;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "WITHSCORES" => with_scores = true,
+            "LIMIT" => {
+                let offset = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+                let count = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+                limit = Limit { offset, count };
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    Ok((with_scores, limit))
+}
+
+// tikv.zrangebyscore key min max and tikv.zrevrangebyscore key max min,
+// both [WITHSCORES] [LIMIT offset count]
+pub fn tikv_zrange_by_score(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let rev = args[0].to_string().to_lowercase().ends_with("zrevrangebyscore");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let first = parse_score_bound(args.next_str()?)?;
+    let second = parse_score_bound(args.next_str()?)?;
+    let (min, max) = if rev { (second, first) } else { (first, second) };
+    let (with_scores, limit) = parse_range_options(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zrange_by_score(key, min, max, rev, with_scores, limit, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+fn parse_lex_bound(value: &[u8]) -> Result<LexBound, RedisError> {
+    match value.first() {
+        Some(b'-') if value.len() == 1 => Ok(LexBound::Min),
+        Some(b'+') if value.len() == 1 => Ok(LexBound::Max),
+        Some(b'[') => Ok(LexBound::Inclusive(value[1..].to_vec())),
+        Some(b'(') => Ok(LexBound::Exclusive(value[1..].to_vec())),
+        _ => Err(RedisError::Str("ERR min or max not valid string range item")),
+    }
+}
+
+// tikv.zrangebylex key min max and tikv.zrevrangebylex key max min, both
+// [LIMIT offset count]
+pub fn tikv_zrange_by_lex(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let rev = args[0].to_string().to_lowercase().ends_with("zrevrangebylex");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let first = parse_lex_bound(args.next_arg()?.as_slice())?;
+    let second = parse_lex_bound(args.next_arg()?.as_slice())?;
+    let (min, max) = if rev { (second, first) } else { (first, second) };
+    let (with_scores, limit) = parse_range_options(&mut args)?;
+    if with_scores {
+        return Err(RedisError::Str("ERR syntax error"));
+    }
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zrange_by_lex(key, min.clone(), max.clone(), rev, limit, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zrangestore dst src min max [BYSCORE|BYLEX] [REV] [LIMIT offset count]
+pub fn tikv_zrangestore(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 5 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let destination = next_key(&mut args)?;
+    let source = next_key(&mut args)?;
+    let first = args.next_arg()?;
+    let second = args.next_arg()?;
+    let (mut by_score, mut by_lex, mut rev) = (false, false, false);
+    let mut limit = None;
+    while let Ok(opt) = args.next_str() {
+        match opt.to_uppercase().as_str() {
+            "BYSCORE" => by_score = true,
+            "BYLEX" => by_lex = true,
+            "REV" => rev = true,
+            "LIMIT" => {
+                let offset = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+                let count = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+                limit = Some(Limit { offset, count });
+            },
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    if by_score && by_lex {
+        return Err(RedisError::Str("ERR syntax error"));
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(RedisError::Str("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"));
+    }
+    // With REV the score and lex bounds come highest first
+    let (low, high) = if rev && (by_score || by_lex) { (second, first) } else { (first, second) };
+    let by = if by_score {
+        RangeBy::Score(parse_score_bound(&low.to_string())?, parse_score_bound(&high.to_string())?)
+    } else if by_lex {
+        RangeBy::Lex(parse_lex_bound(low.as_slice())?, parse_lex_bound(high.as_slice())?)
+    } else {
+        let start = low.to_string().parse::<i64>().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+        let stop = high.to_string().parse::<i64>().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+        RangeBy::Rank(start, stop)
+    };
+    let limit = limit.unwrap_or(Limit::ALL);
+    // Storing a range of the source into itself changes what a replay reads
+    let class = if destination == source { RetryClass::NonIdempotent } else { RetryClass::Write };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(class, || do_async_zrangestore(destination, source, by.clone(), rev, limit)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_zrem(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let members: Vec<String> = args.map(|s| s.to_string()).collect();
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_zrem(key, members.clone())).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zremrangebyscore key min max and tikv.zremrangebyrank key start stop
+pub fn tikv_zrem_range(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let by_rank = args[0].to_string().to_lowercase().ends_with("zremrangebyrank");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let which = if by_rank {
+        let start = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+        let stop = args.next_i64().map_err(|_| RedisError::Str("ERR value is not an integer or out of range"))?;
+        RemoveRange::Rank(start, stop)
+    } else {
+        let min = parse_score_bound(args.next_str()?)?;
+        let max = parse_score_bound(args.next_str()?)?;
+        RemoveRange::Score(min, max)
+    };
+    // The reply counts what the first attempt removed, and by rank a replay
+    // would remove other members once the ranks shifted
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_zrem_range(key, which)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+pub fn tikv_zincrby(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let increment = parse_score(args.next_arg()?.as_slice()).ok_or(RedisError::Str("ERR value is not a valid float"))?;
+    let member = args.next_str()?;
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_zincrby(key, increment, member)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zrank and tikv.zrevrank, both key member [WITHSCORE]
+pub fn tikv_zrank(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() != 3 && args.len() != 4 {
+        return Err(RedisError::WrongArity);
+    }
+    let rev = args[0].to_string().to_lowercase().ends_with("zrevrank");
+    let num_args = args.len();
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let member = args.next_str()?;
+    let with_score = if num_args > 3 {
+        match args.next_str()?.to_uppercase().as_str() {
+            "WITHSCORE" => true,
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    } else {
+        false
+    };
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zrank(key, member, rev, with_score, backend)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zpopmin and tikv.zpopmax, both key [count]
+pub fn tikv_zpop(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    check_writable(ctx, &args)?;
+    if !(2..=3).contains(&args.len()) {
+        return Err(RedisError::WrongArity);
+    }
+    let max = args[0].to_string().to_lowercase().ends_with("zpopmax");
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let count = match args.next_str() {
+        Ok(value) => match value.parse::<i64>() {
+            Ok(count) if count >= 0 => Some(count as u64),
+            _ => return Err(RedisError::Str("ERR value is out of range, must be positive")),
+        },
+        Err(_) => None,
+    };
+    let blocked_client = ctx.block_client();
+    tokio_spawn(async move {
+        let res = with_retry(RetryClass::NonIdempotent, || do_async_zpop(key, count, max)).await;
+        redis_resp(blocked_client, res);
+    });
+    Ok(RedisValue::NoReply)
+}
+
+// tikv.zscan key cursor [MATCH pattern] [COUNT count]
+pub fn tikv_zscan(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 || args.len() % 2 == 0 {
+        return Err(RedisError::WrongArity);
+    }
+    let mut args = args.into_iter().skip(1);
+    let key = next_key(&mut args)?;
+    let cursor = parse_scan_cursor(args.next_str()?)?;
+    let (pattern, count) = parse_scan_options(&mut args)?;
+    let cid = client_id(ctx);
+    let backend = get_read_backend(cid);
+    let blocked_client = ctx.block_client();
+    tokio_spawn_for(cid, async move {
+        let res = with_retry(RetryClass::Read, || do_async_zscan(key, cursor.clone(), pattern.clone(), count, backend)).await;
         redis_resp(blocked_client, res);
     });
     Ok(RedisValue::NoReply)
@@ -0,0 +1,231 @@
+use std::sync::{Arc, RwLock};
+use redis_module::{Context, NextArg, RedisError, RedisResult, RedisValue, RedisString };
+use crate::utils::current_user;
+
+lazy_static! {
+    pub static ref GLOBAL_CONFIG: Arc<RwLock<Config>> = Arc::new(RwLock::new(Config::default()));
+}
+
+// Module settings, given as name/value pairs after the module path in
+// `loadmodule` and changeable at runtime with `tikv.config set`.
+#[derive(Clone)]
+pub struct Config {
+    // Create the RawClient with atomic-for-cas. Nothing calls raw
+    // compare_and_swap since tikv.cas runs in a transaction, the setting is
+    // kept so existing module arguments still load. Takes effect on the
+    // next tikv.conn.
+    pub atomic_cas: bool,
+    // Seconds a deleted string key is kept in the trash namespace before it
+    // is purged. 0 disables the trash and tikv.del deletes immediately.
+    // Collections are never trashed.
+    pub trash_retention: u64,
+    // Reject every command that writes to TiKV, reads keep working
+    pub readonly: bool,
+    // Attempts (including the first one) for read and write commands
+    pub retry_read_attempts: u64,
+    pub retry_write_attempts: u64,
+    // Exponential backoff between attempts, in milliseconds
+    pub retry_backoff_base: u64,
+    pub retry_backoff_cap: u64,
+    // Error classes worth retrying: region, grpc, key
+    pub retry_errors: Vec<String>,
+    // Write commands refused outright, e.g. tikv.delrange
+    pub disabled_commands: Vec<String>,
+    // Write commands only the users in admin_users may run
+    pub admin_commands: Vec<String>,
+    pub admin_users: Vec<String>,
+    // Milliseconds a scan may run before it returns what it has read so far
+    // with a continuation cursor. 0 scans in one request without a budget.
+    pub scan_time_budget: u64,
+    // Bytes of fields and values, or members, tikv.hgetall and
+    // tikv.smembers put in one reply before they stop and return a
+    // continuation cursor. 0 replies with everything at once.
+    pub max_reply_size: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            atomic_cas: false,
+            trash_retention: 0,
+            readonly: false,
+            retry_read_attempts: 1,
+            retry_write_attempts: 1,
+            retry_backoff_base: 10,
+            retry_backoff_cap: 1000,
+            retry_errors: vec![String::from("region"), String::from("grpc")],
+            disabled_commands: Vec::new(),
+            admin_commands: Vec::new(),
+            admin_users: vec![String::from("default")],
+            scan_time_budget: 0,
+            max_reply_size: 0,
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "on" | "true" | "1" => Ok(true),
+        "no" | "off" | "false" | "0" => Ok(false),
+        _ => Err(format!("invalid boolean value '{}'", value)),
+    }
+}
+
+fn parse_u64(value: &str) -> Result<u64, String> {
+    value.parse::<u64>().map_err(|_| format!("invalid integer value '{}'", value))
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim().to_owned()).filter(|s| !s.is_empty()).collect()
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "yes".to_owned() } else { "no".to_owned() }
+}
+
+impl Config {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_lowercase().as_str() {
+            "atomic-cas" => self.atomic_cas = parse_bool(value)?,
+            "trash-retention" => self.trash_retention = parse_u64(value)?,
+            "readonly" => self.readonly = parse_bool(value)?,
+            "retry-read-attempts" => self.retry_read_attempts = parse_u64(value)?.max(1),
+            "retry-write-attempts" => self.retry_write_attempts = parse_u64(value)?.max(1),
+            "retry-backoff-base" => self.retry_backoff_base = parse_u64(value)?,
+            "retry-backoff-cap" => self.retry_backoff_cap = parse_u64(value)?,
+            "retry-errors" => self.retry_errors = parse_list(value),
+            "disabled-commands" => self.disabled_commands = parse_list(value),
+            "admin-commands" => self.admin_commands = parse_list(value),
+            "admin-users" => self.admin_users = parse_list(value),
+            "scan-time-budget" => self.scan_time_budget = parse_u64(value)?,
+            "max-reply-size" => self.max_reply_size = parse_u64(value)?,
+            _ => return Err(format!("unknown config '{}'", name)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        match name.to_lowercase().as_str() {
+            "atomic-cas" => Some(format_bool(self.atomic_cas)),
+            "trash-retention" => Some(self.trash_retention.to_string()),
+            "readonly" => Some(format_bool(self.readonly)),
+            "retry-read-attempts" => Some(self.retry_read_attempts.to_string()),
+            "retry-write-attempts" => Some(self.retry_write_attempts.to_string()),
+            "retry-backoff-base" => Some(self.retry_backoff_base.to_string()),
+            "retry-backoff-cap" => Some(self.retry_backoff_cap.to_string()),
+            "retry-errors" => Some(self.retry_errors.join(",")),
+            "disabled-commands" => Some(self.disabled_commands.join(",")),
+            "admin-commands" => Some(self.admin_commands.join(",")),
+            "admin-users" => Some(self.admin_users.join(",")),
+            "scan-time-budget" => Some(self.scan_time_budget.to_string()),
+            "max-reply-size" => Some(self.max_reply_size.to_string()),
+            _ => None,
+        }
+    }
+}
+
+pub fn get_config() -> Config {
+    GLOBAL_CONFIG.read().unwrap().clone()
+}
+
+fn contains_command(list: &[String], name: &str) -> bool {
+    list.iter().any(|c| c.eq_ignore_ascii_case(name))
+}
+
+// Apply disabled-commands and admin-commands to the command `args` were
+// given to, looking at the name it was invoked as
+fn check_command_policy(ctx: &Context, config: &Config, args: &[RedisString]) -> Result<(), RedisError> {
+    let name = args[0].to_string();
+    if contains_command(&config.disabled_commands, &name) {
+        return Err(RedisError::String(format!("ERR command '{}' is disabled", name)));
+    }
+    if contains_command(&config.admin_commands, &name) && !config.admin_users.contains(&current_user(ctx)) {
+        return Err(RedisError::String(format!("NOPERM command '{}' is restricted to admin users", name)));
+    }
+    Ok(())
+}
+
+// Called first by every command that writes to TiKV, with the command's
+// arguments so the policy can look at the name it was invoked as.
+pub fn check_writable(ctx: &Context, args: &[RedisString]) -> Result<(), RedisError> {
+    let config = GLOBAL_CONFIG.read().unwrap();
+    if config.readonly {
+        return Err(RedisError::Str("READONLY TiKV is in read-only mode, see tikv.readonly"));
+    }
+    check_command_policy(ctx, &config, args)
+}
+
+// Called first by the commands that manage connections and settings: they
+// can be disabled or restricted like writes, but keep working in read-only
+// mode
+pub fn check_allowed(ctx: &Context, args: &[RedisString]) -> Result<(), RedisError> {
+    check_command_policy(ctx, &GLOBAL_CONFIG.read().unwrap(), args)
+}
+
+// Changing settings could lift the policy itself, so it is reserved to
+// admin users whatever admin-commands says
+fn check_admin(ctx: &Context, args: &[RedisString]) -> Result<(), RedisError> {
+    let config = GLOBAL_CONFIG.read().unwrap();
+    check_command_policy(ctx, &config, args)?;
+    if !config.admin_users.contains(&current_user(ctx)) {
+        let name = args[0].to_string();
+        return Err(RedisError::String(format!("NOPERM command '{}' is restricted to admin users", name)));
+    }
+    Ok(())
+}
+
+// Apply `loadmodule` arguments, which come in name/value pairs
+pub fn load_config(args: &[RedisString]) -> Result<(), String> {
+    if args.len() % 2 != 0 {
+        return Err(String::from("module arguments must be name/value pairs"));
+    }
+    let mut config = GLOBAL_CONFIG.write().unwrap();
+    for pair in args.chunks(2) {
+        config.set(&pair[0].to_string(), &pair[1].to_string())?;
+    }
+    Ok(())
+}
+
+pub fn tikv_config(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() < 3 {
+        return Err(RedisError::WrongArity);
+    }
+    if args[1].to_string().eq_ignore_ascii_case("SET") {
+        check_admin(ctx, &args)?;
+    }
+    let mut args = args.into_iter().skip(1);
+    let sub = args.next_str()?.to_uppercase();
+    let name = args.next_str()?;
+    match sub.as_str() {
+        "GET" => match GLOBAL_CONFIG.read().unwrap().get(name) {
+            Some(value) => Ok(RedisValue::Array(vec![name.into(), value.into()])),
+            None => Ok(RedisValue::Array(vec![])),
+        },
+        "SET" => {
+            let value = args.next_str()?;
+            match GLOBAL_CONFIG.write().unwrap().set(name, value) {
+                Ok(_) => Ok("OK".into()),
+                Err(err) => Err(RedisError::String(format!("ERR {}", err))),
+            }
+        },
+        _ => Err(RedisError::Str("ERR unknown subcommand, use GET or SET")),
+    }
+}
+
+pub fn tikv_readonly(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    if args.len() > 2 {
+        return Err(RedisError::WrongArity);
+    }
+    if args.len() == 2 {
+        check_admin(ctx, &args)?;
+        let mut args = args.into_iter().skip(1);
+        let readonly = match args.next_str()?.to_uppercase().as_str() {
+            "ON" => true,
+            "OFF" => false,
+            _ => return Err(RedisError::Str("ERR syntax error, use ON or OFF")),
+        };
+        GLOBAL_CONFIG.write().unwrap().readonly = readonly;
+    }
+    let state = if GLOBAL_CONFIG.read().unwrap().readonly { "on" } else { "off" };
+    Ok(state.into())
+}
@@ -0,0 +1,485 @@
+// Keys the module writes for its own bookkeeping start with a zero byte and
+// a one-letter namespace, so they never mix with plain user string keys.
+const INTERNAL_PREFIX: u8 = 0x00;
+const TRASH_NS: u8 = b'D';
+const META_NS: u8 = b'M';
+const HASH_NS: u8 = b'H';
+const FIELD_TTL_NS: u8 = b'T';
+const LIST_NS: u8 = b'L';
+const SET_NS: u8 = b'S';
+const ZSET_NS: u8 = b'Z';
+const ZSET_SCORE_NS: u8 = b'O';
+
+fn namespace_key(ns: u8, key: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(key.len() + 2);
+    raw.push(INTERNAL_PREFIX);
+    raw.push(ns);
+    raw.extend_from_slice(key);
+    raw
+}
+
+// [start, end) covering every key of a namespace
+fn namespace_range(ns: u8) -> (Vec<u8>, Vec<u8>) {
+    (vec![INTERNAL_PREFIX, ns], vec![INTERNAL_PREFIX, ns + 1])
+}
+
+// First key a user can store, everything below belongs to the module
+pub fn user_range_start(start: &[u8]) -> Vec<u8> {
+    match start.first() {
+        None | Some(&INTERNAL_PREFIX) => vec![INTERNAL_PREFIX + 1],
+        _ => start.to_vec(),
+    }
+}
+
+pub fn encode_trash_key(key: &[u8]) -> Vec<u8> {
+    namespace_key(TRASH_NS, key)
+}
+
+pub fn trash_range() -> (Vec<u8>, Vec<u8>) {
+    namespace_range(TRASH_NS)
+}
+
+// Trash values are the deletion time in milliseconds (big endian) followed
+// by the original value.
+pub fn encode_trash_value(deleted_at: u64, value: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(value.len() + 8);
+    raw.extend_from_slice(&deleted_at.to_be_bytes());
+    raw.extend_from_slice(value);
+    raw
+}
+
+pub fn decode_trash_value(raw: &[u8]) -> Option<(u64, &[u8])> {
+    if raw.len() < 8 {
+        return None;
+    }
+    let mut ts = [0u8; 8];
+    ts.copy_from_slice(&raw[..8]);
+    Some((u64::from_be_bytes(ts), &raw[8..]))
+}
+
+// Smallest key greater than every key starting with `prefix`, or None when
+// no such key exists (empty prefix or all 0xff bytes)
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+// A key as the transactional API stores it in TiKV, which is also how PD
+// reports region bounds: the bytes in groups of 8, each padded with zeros
+// and followed by 0xff minus the padding length (memcomparable format)
+pub fn encode_memcomparable(key: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((key.len() / 8 + 1) * 9);
+    let mut rest = key;
+    loop {
+        let take = rest.len().min(8);
+        raw.extend_from_slice(&rest[..take]);
+        raw.resize(raw.len() + 8 - take, 0);
+        raw.push(0xff - (8 - take) as u8);
+        if take < 8 {
+            return raw;
+        }
+        rest = &rest[8..];
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DataType {
+    String,
+    Hash,
+    List,
+    Set,
+    ZSet,
+}
+
+impl DataType {
+    fn to_byte(self) -> u8 {
+        match self {
+            DataType::String => 0,
+            DataType::Hash => 1,
+            DataType::List => 2,
+            DataType::Set => 3,
+            DataType::ZSet => 4,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<DataType> {
+        match b {
+            0 => Some(DataType::String),
+            1 => Some(DataType::Hash),
+            2 => Some(DataType::List),
+            3 => Some(DataType::Set),
+            4 => Some(DataType::ZSet),
+            _ => None,
+        }
+    }
+
+    // Name Redis TYPE reports
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::String => "string",
+            DataType::Hash => "hash",
+            DataType::List => "list",
+            DataType::Set => "set",
+            DataType::ZSet => "zset",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<DataType> {
+        match name.to_lowercase().as_str() {
+            "string" => Some(DataType::String),
+            "hash" => Some(DataType::Hash),
+            "list" => Some(DataType::List),
+            "set" => Some(DataType::Set),
+            "zset" => Some(DataType::ZSet),
+            _ => None,
+        }
+    }
+}
+
+// Per-key metadata stored under the meta namespace. Strings only get an
+// entry when they carry an expiration, every other type always has one.
+#[derive(Clone, Copy)]
+pub struct KeyMeta {
+    pub data_type: DataType,
+    // Expiration as Unix time in milliseconds, 0 for none
+    pub expire_at: u64,
+    // Number of fields or elements for collection types, 0 for strings
+    pub size: u64,
+    // Sequence number of a list's first element, 0 for other types
+    pub head: u64,
+}
+
+impl KeyMeta {
+    pub fn new(data_type: DataType, expire_at: u64) -> KeyMeta {
+        let head = if data_type == DataType::List { LIST_ORIGIN } else { 0 };
+        KeyMeta { data_type, expire_at, size: 0, head }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expire_at != 0 && self.expire_at <= now
+    }
+}
+
+pub fn encode_meta_key(key: &[u8]) -> Vec<u8> {
+    namespace_key(META_NS, key)
+}
+
+// User key a meta key belongs to
+pub fn decode_meta_key(raw: &[u8]) -> &[u8] {
+    &raw[2..]
+}
+
+pub fn meta_range() -> (Vec<u8>, Vec<u8>) {
+    namespace_range(META_NS)
+}
+
+// Type byte followed by the expiration and the size (big endian), lists
+// add their head
+pub fn encode_meta(meta: &KeyMeta) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(25);
+    raw.push(meta.data_type.to_byte());
+    raw.extend_from_slice(&meta.expire_at.to_be_bytes());
+    raw.extend_from_slice(&meta.size.to_be_bytes());
+    if meta.data_type == DataType::List {
+        raw.extend_from_slice(&meta.head.to_be_bytes());
+    }
+    raw
+}
+
+pub fn decode_meta(raw: &[u8]) -> Option<KeyMeta> {
+    if raw.len() < 17 {
+        return None;
+    }
+    let data_type = DataType::from_byte(raw[0])?;
+    let mut ts = [0u8; 8];
+    ts.copy_from_slice(&raw[1..9]);
+    let mut meta = KeyMeta::new(data_type, u64::from_be_bytes(ts));
+    let mut size = [0u8; 8];
+    size.copy_from_slice(&raw[9..17]);
+    meta.size = u64::from_be_bytes(size);
+    if raw.len() >= 25 {
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&raw[17..25]);
+        meta.head = u64::from_be_bytes(head);
+    }
+    Some(meta)
+}
+
+// Namespace, key length (u32 big endian) and key, the prefix of every
+// entry a collection stores besides its meta entry
+fn collection_prefix(ns: u8, key: &[u8]) -> Vec<u8> {
+    let mut raw = namespace_key(ns, &(key.len() as u32).to_be_bytes());
+    raw.extend_from_slice(key);
+    raw
+}
+
+// [start, end) covering every entry of a collection
+fn collection_range(ns: u8, key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let start = collection_prefix(ns, key);
+    // The namespace byte is below 0xff, so the prefix always has an end
+    let end = prefix_end(&start).unwrap();
+    (start, end)
+}
+
+// Hash fields are stored under the hash key's length, the hash key and
+// then the field, so a key's fields can never run into the fields of
+// another key whatever bytes either name contains.
+fn hash_prefix(key: &[u8]) -> Vec<u8> {
+    collection_prefix(HASH_NS, key)
+}
+
+pub fn encode_hash_key(key: &[u8], field: &[u8]) -> Vec<u8> {
+    let mut raw = hash_prefix(key);
+    raw.extend_from_slice(field);
+    raw
+}
+
+// Field name of an encoded hash key belonging to `key`
+pub fn decode_hash_field<'a>(key: &[u8], raw: &'a [u8]) -> &'a [u8] {
+    &raw[2 + 4 + key.len()..]
+}
+
+// Hash key and field name of an encoded hash key
+pub fn split_hash_key(raw: &[u8]) -> Option<(&[u8], &[u8])> {
+    if raw.len() < 6 {
+        return None;
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&raw[2..6]);
+    let len = u32::from_be_bytes(len) as usize;
+    if raw.len() < 6 + len {
+        return None;
+    }
+    Some((&raw[6..6 + len], &raw[6 + len..]))
+}
+
+// [start, end) covering every field of a hash
+pub fn hash_range(key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    collection_range(HASH_NS, key)
+}
+
+// Hash field values start with a header byte: 0 for a plain value, 1 when
+// the field expires, followed by the expiration (Unix ms, big endian)
+// before the value itself.
+pub fn encode_field_value(value: &[u8], expire_at: u64) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(value.len() + 9);
+    if expire_at == 0 {
+        raw.push(0);
+    } else {
+        raw.push(1);
+        raw.extend_from_slice(&expire_at.to_be_bytes());
+    }
+    raw.extend_from_slice(value);
+    raw
+}
+
+// Value and expiration (0 for none) of a stored hash field
+pub fn decode_field_value(raw: &[u8]) -> Option<(&[u8], u64)> {
+    match raw.first()? {
+        0 => Some((&raw[1..], 0)),
+        1 if raw.len() >= 9 => {
+            let mut ts = [0u8; 8];
+            ts.copy_from_slice(&raw[1..9]);
+            Some((&raw[9..], u64::from_be_bytes(ts)))
+        },
+        _ => None,
+    }
+}
+
+// Every hash field with an expiration has an entry in the field TTL index,
+// keyed like the field itself and holding the expiration, so the
+// background purge finds them without walking every hash.
+pub fn encode_field_ttl_key(field_key: &[u8]) -> Vec<u8> {
+    namespace_key(FIELD_TTL_NS, &field_key[2..])
+}
+
+pub fn decode_field_ttl_key(raw: &[u8]) -> Vec<u8> {
+    namespace_key(HASH_NS, &raw[2..])
+}
+
+pub fn field_ttl_range() -> (Vec<u8>, Vec<u8>) {
+    namespace_range(FIELD_TTL_NS)
+}
+
+pub fn decode_field_ttl(raw: &[u8]) -> Option<u64> {
+    let ts: [u8; 8] = raw.try_into().ok()?;
+    Some(u64::from_be_bytes(ts))
+}
+
+// List elements are stored under the list key's length, the key and the
+// element's sequence number (u64 big endian), so they scan in list order.
+// A new list starts in the middle of the sequence space, leaving room to
+// push on both ends.
+pub const LIST_ORIGIN: u64 = 1 << 63;
+
+pub fn encode_list_key(key: &[u8], seq: u64) -> Vec<u8> {
+    let mut raw = collection_prefix(LIST_NS, key);
+    raw.extend_from_slice(&seq.to_be_bytes());
+    raw
+}
+
+pub fn list_range(key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    collection_range(LIST_NS, key)
+}
+
+// Set members are stored like hash fields under their own namespace, with
+// an empty value: the member is all there is to store.
+pub fn encode_set_key(key: &[u8], member: &[u8]) -> Vec<u8> {
+    let mut raw = collection_prefix(SET_NS, key);
+    raw.extend_from_slice(member);
+    raw
+}
+
+// Member of an encoded set key belonging to `key`
+pub fn decode_set_member<'a>(key: &[u8], raw: &'a [u8]) -> &'a [u8] {
+    &raw[2 + 4 + key.len()..]
+}
+
+pub fn set_range(key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    collection_range(SET_NS, key)
+}
+
+// Sorted sets use two keyspaces kept in step: member -> score, to look up
+// a member's score, and an index of score followed by member with empty
+// values, which scans in (score, member) order. Scores are stored in a
+// memcomparable form: the sign bit flipped for positive numbers and every
+// bit for negative ones, so byte order is numeric order.
+pub fn encode_score(score: f64) -> [u8; 8] {
+    // -0.0 and 0.0 are the same score
+    let bits = if score == 0.0 { 0 } else { score.to_bits() };
+    let bits = if bits >> 63 == 1 { !bits } else { bits | 1 << 63 };
+    bits.to_be_bytes()
+}
+
+pub fn decode_score(raw: &[u8]) -> Option<f64> {
+    let bits = u64::from_be_bytes(raw.get(..8)?.try_into().ok()?);
+    let bits = if bits >> 63 == 1 { bits & !(1 << 63) } else { !bits };
+    Some(f64::from_bits(bits))
+}
+
+pub fn encode_zset_member_key(key: &[u8], member: &[u8]) -> Vec<u8> {
+    let mut raw = collection_prefix(ZSET_NS, key);
+    raw.extend_from_slice(member);
+    raw
+}
+
+// Member of an encoded member key belonging to `key`
+pub fn decode_zset_member<'a>(key: &[u8], raw: &'a [u8]) -> &'a [u8] {
+    &raw[2 + 4 + key.len()..]
+}
+
+pub fn zset_member_range(key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    collection_range(ZSET_NS, key)
+}
+
+// Start of the index entries with `score`, followed by the member
+pub fn encode_zset_score_key(key: &[u8], score: f64, member: &[u8]) -> Vec<u8> {
+    let mut raw = collection_prefix(ZSET_SCORE_NS, key);
+    raw.extend_from_slice(&encode_score(score));
+    raw.extend_from_slice(member);
+    raw
+}
+
+// Score and member of an index entry belonging to `key`
+pub fn decode_zset_score_key<'a>(key: &[u8], raw: &'a [u8]) -> Option<(f64, &'a [u8])> {
+    let rest = &raw[2 + 4 + key.len()..];
+    Some((decode_score(rest)?, &rest[8..]))
+}
+
+pub fn zset_score_range(key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    collection_range(ZSET_SCORE_NS, key)
+}
+
+// First index key of the entries scoring at least `score` (more than
+// `score` when not `inclusive`)
+pub fn zset_score_start(key: &[u8], score: f64, inclusive: bool) -> Vec<u8> {
+    let prefix = encode_zset_score_key(key, score, b"");
+    if inclusive {
+        return prefix;
+    }
+    // The namespace byte is below 0xff, so the prefix always has an end
+    prefix_end(&prefix).unwrap()
+}
+
+// End (exclusive) of the index entries scoring at most `score` (less than
+// `score` when not `inclusive`)
+pub fn zset_score_end(key: &[u8], score: f64, inclusive: bool) -> Vec<u8> {
+    let prefix = encode_zset_score_key(key, score, b"");
+    if !inclusive {
+        return prefix;
+    }
+    prefix_end(&prefix).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_round_trip() {
+        for score in [f64::NEG_INFINITY, -1e300, -1.5, -0.0, 0.0, 1e-300, 2.5, f64::INFINITY] {
+            assert_eq!(decode_score(&encode_score(score)), Some(score));
+        }
+    }
+
+    #[test]
+    fn scores_sort_by_bytes() {
+        let scores = [f64::NEG_INFINITY, -2.0, -0.5, 0.0, 0.5, 2.0, f64::INFINITY];
+        for pair in scores.windows(2) {
+            assert!(encode_score(pair[0]) < encode_score(pair[1]));
+        }
+        assert_eq!(encode_score(-0.0), encode_score(0.0));
+    }
+
+    #[test]
+    fn memcomparable_groups() {
+        assert_eq!(encode_memcomparable(b""), vec![0, 0, 0, 0, 0, 0, 0, 0, 0xf7]);
+        assert_eq!(encode_memcomparable(b"abc"), vec![b'a', b'b', b'c', 0, 0, 0, 0, 0, 0xfa]);
+        let full = encode_memcomparable(b"12345678");
+        assert_eq!(&full[..9], b"12345678\xff");
+        assert_eq!(&full[9..], &[0, 0, 0, 0, 0, 0, 0, 0, 0xf7]);
+    }
+
+    #[test]
+    fn memcomparable_keeps_order() {
+        let keys: [&[u8]; 6] = [b"", b"a", b"a\x00", b"abcdefgh", b"abcdefgh\x00", b"b"];
+        for pair in keys.windows(2) {
+            assert!(encode_memcomparable(pair[0]) < encode_memcomparable(pair[1]));
+        }
+    }
+
+    #[test]
+    fn meta_round_trip() {
+        let mut list = KeyMeta::new(DataType::List, 1_700_000_000_000);
+        list.size = 3;
+        list.head = LIST_ORIGIN - 2;
+        let decoded = decode_meta(&encode_meta(&list)).unwrap();
+        assert!(decoded.data_type == DataType::List);
+        assert_eq!((decoded.expire_at, decoded.size, decoded.head), (1_700_000_000_000, 3, LIST_ORIGIN - 2));
+
+        let mut hash = KeyMeta::new(DataType::Hash, 0);
+        hash.size = 7;
+        let raw = encode_meta(&hash);
+        assert_eq!(raw.len(), 17);
+        let decoded = decode_meta(&raw).unwrap();
+        assert!(decoded.data_type == DataType::Hash);
+        assert_eq!((decoded.expire_at, decoded.size, decoded.head), (0, 7, 0));
+    }
+
+    #[test]
+    fn decode_meta_rejects_short_and_unknown() {
+        assert!(decode_meta(&[]).is_none());
+        let raw = encode_meta(&KeyMeta::new(DataType::Set, 5));
+        assert!(decode_meta(&raw[..9]).is_none());
+        assert!(decode_meta(&raw[..16]).is_none());
+        let mut unknown = raw.clone();
+        unknown[0] = 0xee;
+        assert!(decode_meta(&unknown).is_none());
+    }
+}
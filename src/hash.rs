@@ -0,0 +1,662 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use redis_module::{ RedisValue };
+use tikv_client::{ Error, KvPair, Snapshot, Transaction };
+use crate::encoding::*;
+use crate::tikv::{ ReadBackend, get_snapshot, type_keys, expect_type, paged_reply };
+use crate::config::get_config;
+use crate::txn::{ Reader, begin_txn, delete_range, finish_txn };
+use crate::numeric::incr_float;
+use crate::utils::{ now_millis, random_below, shuffle };
+
+// A hash is a meta entry of type Hash, which also counts the fields, plus
+// one entry per field, see encode_hash_key. The meta entry exists as long
+// as the hash has fields. Field values carry their own expiration, see
+// encode_field_value; an expired field reads as missing until the
+// background purge deletes it, and is counted by the meta entry until then.
+
+const HASH_PAGE_SIZE: u32 = 1000;
+
+// Value and expiration of a stored field, None when missing or expired
+fn live_field(raw: Option<&Vec<u8>>, now: u64) -> Option<(Vec<u8>, u64)> {
+    let (value, expire_at) = decode_field_value(raw?)?;
+    if expire_at != 0 && expire_at <= now {
+        return None;
+    }
+    Some((value.to_vec(), expire_at))
+}
+
+// Delete every field of the hash inside `txn`
+pub async fn delete_hash_fields(txn: &mut Transaction, key: &[u8]) -> Result<(), Error> {
+    delete_range(txn, hash_range(key)).await
+}
+
+async fn hset(txn: &mut Transaction, key: &[u8], pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<i64, Error> {
+    let field_keys: Vec<Vec<u8>> = pairs.iter().map(|(field, _)| encode_hash_key(key, field)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(field_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let meta = expect_type(&found, key, DataType::Hash)?;
+
+    // Expired fields count as added but are already in the meta size
+    let now = now_millis();
+    let mut added: HashSet<Vec<u8>> = HashSet::new();
+    let mut stored: HashSet<Vec<u8>> = HashSet::new();
+    for (field_key, (_, value)) in field_keys.into_iter().zip(pairs) {
+        if meta.is_none() || live_field(found.get(&field_key), now).is_none() {
+            added.insert(field_key.clone());
+        }
+        if meta.is_none() || !found.contains_key(&field_key) {
+            stored.insert(field_key.clone());
+        }
+        // Writing a field drops its expiration, as in Redis
+        txn.put(field_key, encode_field_value(&value, 0)).await?;
+    }
+    if meta.is_none() || !stored.is_empty() {
+        let mut meta = meta.unwrap_or_else(|| KeyMeta::new(DataType::Hash, 0));
+        meta.size += stored.len() as u64;
+        txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    }
+    Ok(added.len() as i64)
+}
+
+// Set the fields, replies how many of them are new
+pub async fn do_async_hset(key: &str, pairs: Vec<(String, String)>) -> Result<RedisValue, Error> {
+    let pairs = pairs.into_iter().map(|(f, v)| (f.into_bytes(), v.into_bytes())).collect();
+    let mut txn = begin_txn().await?;
+    let res = hset(&mut txn, key.as_bytes(), pairs).await;
+    let added = finish_txn(txn, res).await?;
+    Ok(added.into())
+}
+
+// Delete the fields of the hash under `key` and shrink its meta entry.
+// `found` must hold the result of reading type_keys(key) and the field
+// keys. Returns how many of the fields were live.
+async fn delete_fields(txn: &mut Transaction, key: &[u8], field_keys: Vec<Vec<u8>>, found: &HashMap<Vec<u8>, Vec<u8>>) -> Result<i64, Error> {
+    let mut meta = match expect_type(found, key, DataType::Hash)? {
+        Some(meta) => meta,
+        None => return Ok(0),
+    };
+
+    let now = now_millis();
+    let removed: HashSet<Vec<u8>> = field_keys.into_iter().filter(|k| found.contains_key(k)).collect();
+    if removed.is_empty() {
+        return Ok(0);
+    }
+    let mut live = 0;
+    for field_key in removed.iter() {
+        if live_field(found.get(field_key), now).is_some() {
+            live += 1;
+        }
+        txn.delete(field_key.clone()).await?;
+    }
+    meta.size = meta.size.saturating_sub(removed.len() as u64);
+    if meta.size == 0 {
+        txn.delete(encode_meta_key(key)).await?;
+    } else {
+        txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    }
+    Ok(live)
+}
+
+async fn hdel(txn: &mut Transaction, key: &[u8], fields: Vec<Vec<u8>>) -> Result<i64, Error> {
+    let field_keys: Vec<Vec<u8>> = fields.iter().map(|field| encode_hash_key(key, field)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(field_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    delete_fields(txn, key, field_keys, &found).await
+}
+
+// Delete the fields, replies how many of them existed
+pub async fn do_async_hdel(key: &str, fields: Vec<String>) -> Result<RedisValue, Error> {
+    let fields = fields.into_iter().map(|f| f.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = hdel(&mut txn, key.as_bytes(), fields).await;
+    let removed = finish_txn(txn, res).await?;
+    Ok(removed.into())
+}
+
+async fn hbatchdel(txn: &mut Transaction, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<i64, Error> {
+    let mut by_key: BTreeMap<Vec<u8>, Vec<Vec<u8>>> = BTreeMap::new();
+    for (key, field) in pairs.into_iter() {
+        let field_key = encode_hash_key(&key, &field);
+        by_key.entry(key).or_default().push(field_key);
+    }
+    let mut wanted = Vec::new();
+    for (key, field_keys) in by_key.iter() {
+        wanted.extend(type_keys(key));
+        wanted.extend(field_keys.iter().cloned());
+    }
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let mut removed = 0;
+    for (key, field_keys) in by_key.into_iter() {
+        removed += delete_fields(txn, &key, field_keys, &found).await?;
+    }
+    Ok(removed)
+}
+
+// Delete fields spread over several hashes in one transaction, reading
+// every hash and field with a single batch_get. Replies how many of the
+// fields existed. A key of another type fails the whole command.
+pub async fn do_async_hbatchdel(pairs: Vec<(String, String)>) -> Result<RedisValue, Error> {
+    let pairs = pairs.into_iter().map(|(k, f)| (k.into_bytes(), f.into_bytes())).collect();
+    let mut txn = begin_txn().await?;
+    let res = hbatchdel(&mut txn, pairs).await;
+    let removed = finish_txn(txn, res).await?;
+    Ok(removed.into())
+}
+
+async fn hsetnx(txn: &mut Transaction, key: &[u8], field: &[u8], value: Vec<u8>) -> Result<bool, Error> {
+    let field_key = encode_hash_key(key, field);
+    let mut wanted = type_keys(key);
+    wanted.push(field_key.clone());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let meta = expect_type(&found, key, DataType::Hash)?;
+    if meta.is_some() && live_field(found.get(&field_key), now_millis()).is_some() {
+        return Ok(false);
+    }
+    let is_new = meta.is_none() || !found.contains_key(&field_key);
+    txn.put(field_key, encode_field_value(&value, 0)).await?;
+    if is_new {
+        let mut meta = meta.unwrap_or_else(|| KeyMeta::new(DataType::Hash, 0));
+        meta.size += 1;
+        txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    }
+    Ok(true)
+}
+
+// Set the field only if it does not exist yet, replies 1 or 0
+pub async fn do_async_hsetnx(key: &str, field: &str, val: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = hsetnx(&mut txn, key.as_bytes(), field.as_bytes(), val.as_bytes().to_vec()).await;
+    let written = finish_txn(txn, res).await?;
+    Ok((written as i64).into())
+}
+
+// Read-modify-write of one field inside `txn`, creating the hash or the
+// field when missing. `update` gets the current value (None when missing)
+// and returns the new value together with what the command replies. The
+// field keeps its expiration.
+pub async fn modify_field<T, F>(txn: &mut Transaction, key: &[u8], field: &[u8], update: F) -> Result<T, Error>
+where
+    F: FnOnce(Option<Vec<u8>>) -> Result<(Vec<u8>, T), Error>,
+{
+    let field_key = encode_hash_key(key, field);
+    let mut wanted = type_keys(key);
+    wanted.push(field_key.clone());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let meta = expect_type(&found, key, DataType::Hash)?;
+    let (old, expire_at) = match live_field(found.get(&field_key), now_millis()) {
+        Some((value, expire_at)) if meta.is_some() => (Some(value), expire_at),
+        _ => (None, 0),
+    };
+    let is_new = meta.is_none() || !found.contains_key(&field_key);
+    let (value, reply) = update(old)?;
+    txn.put(field_key, encode_field_value(&value, expire_at)).await?;
+    if is_new {
+        let mut meta = meta.unwrap_or_else(|| KeyMeta::new(DataType::Hash, 0));
+        meta.size += 1;
+        txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    }
+    Ok(reply)
+}
+
+// Add `delta` to the integer in the field (0 when missing), replies the
+// new value
+pub async fn do_async_hincr_by(key: &str, field: &str, delta: i64) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = modify_field(&mut txn, key.as_bytes(), field.as_bytes(), |old| {
+        let current = match old {
+            Some(raw) => std::str::from_utf8(&raw).ok().and_then(|v| v.parse::<i64>().ok()).ok_or_else(|| {
+                tikv_client::Error::StringError(String::from("ERR hash value is not an integer"))
+            })?,
+            None => 0,
+        };
+        let value = current.checked_add(delta).ok_or_else(|| {
+            tikv_client::Error::StringError(String::from("ERR increment or decrement would overflow"))
+        })?;
+        Ok((value.to_string().into_bytes(), value))
+    }).await;
+    let value = finish_txn(txn, res).await?;
+    Ok(value.into())
+}
+
+pub async fn do_async_hincr_by_float(key: &str, field: &str, delta: f64) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = modify_field(&mut txn, key.as_bytes(), field.as_bytes(), |old| incr_float(old, delta, "ERR hash value is not a float")).await;
+    let value = finish_txn(txn, res).await?;
+    Ok(value.into())
+}
+
+// Field count from the meta entry, a single point read. Expired fields
+// count until the background purge deletes them.
+pub async fn do_async_hlen(key: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let size = expect_type(&found, key, DataType::Hash)?.map_or(0, |m| m.size);
+    Ok((size as i64).into())
+}
+
+// Value of one field, read together with the key's type entries
+async fn read_field(key: &[u8], field: &[u8], backend: ReadBackend) -> Result<Option<Vec<u8>>, Error> {
+    let field_key = encode_hash_key(key, field);
+    let mut wanted = type_keys(key);
+    wanted.push(field_key.clone());
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    if expect_type(&found, key, DataType::Hash)?.is_none() {
+        return Ok(None);
+    }
+    Ok(live_field(found.get(&field_key), now_millis()).map(|(value, _)| value))
+}
+
+pub async fn do_async_hget(key: &str, field: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let value = read_field(key.as_bytes(), field.as_bytes(), backend).await?;
+    Ok(value.into())
+}
+
+pub async fn do_async_hstrlen(key: &str, field: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let value = read_field(key.as_bytes(), field.as_bytes(), backend).await?;
+    Ok(value.map_or(0, |v| v.len()).into())
+}
+
+// Values of the fields in request order, nil for missing fields
+pub async fn do_async_hmget(key: &str, fields: Vec<String>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let field_keys: Vec<Vec<u8>> = fields.iter().map(|field| encode_hash_key(key, field.as_bytes())).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(field_keys.iter().cloned());
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    let exists = expect_type(&found, key, DataType::Hash)?.is_some();
+    let now = now_millis();
+    let values: Vec<RedisValue> = field_keys.iter().map(|k| {
+        match live_field(found.get(k), now) {
+            Some((value, _)) if exists => value.into(),
+            _ => RedisValue::Null,
+        }
+    }).collect();
+    Ok(values.into())
+}
+
+// Values of fields of several hashes, one array per hash in request
+// order, nil for missing fields. Every hash and field is read with a single
+// batch_get from one snapshot.
+pub async fn do_async_hmget_multi(requests: Vec<(String, Vec<String>)>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let mut wanted = Vec::new();
+    for (key, fields) in requests.iter() {
+        let key = key.as_bytes();
+        wanted.extend(type_keys(key));
+        wanted.extend(fields.iter().map(|field| encode_hash_key(key, field.as_bytes())));
+    }
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    let now = now_millis();
+    let mut replies = Vec::with_capacity(requests.len());
+    for (key, fields) in requests.iter() {
+        let key = key.as_bytes();
+        let exists = expect_type(&found, key, DataType::Hash)?.is_some();
+        let values: Vec<RedisValue> = fields.iter().map(|field| {
+            match live_field(found.get(&encode_hash_key(key, field.as_bytes())), now) {
+                Some((value, _)) if exists => value.into(),
+                _ => RedisValue::Null,
+            }
+        }).collect();
+        replies.push(RedisValue::Array(values));
+    }
+    Ok(RedisValue::Array(replies))
+}
+
+// Values of (hash, field) pairs with a single batch_get, None for missing
+// fields and for keys that are not hashes
+pub async fn read_hash_values(reader: &mut Reader<'_>, pairs: &[(Vec<u8>, Vec<u8>)]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let mut wanted = Vec::new();
+    for (key, field) in pairs.iter() {
+        wanted.extend(type_keys(key));
+        wanted.push(encode_hash_key(key, field));
+    }
+    let found = reader.batch_get(wanted).await?;
+    let now = now_millis();
+    Ok(pairs.iter().map(|(key, field)| {
+        match expect_type(&found, key, DataType::Hash) {
+            Ok(Some(_)) => live_field(found.get(&encode_hash_key(key, field)), now).map(|(value, _)| value),
+            _ => None,
+        }
+    }).collect())
+}
+
+// Live fields and values from the field key `start` on, scanned page by
+// page. With a `max_bytes` other than 0 it stops before the field that
+// would take the fields and values past it, and also returns that field
+// to continue from. The first field is always taken.
+async fn fields_from(reader: &mut Reader<'_>, key: &[u8], mut start: Vec<u8>, max_bytes: u64) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>), Error> {
+    let now = now_millis();
+    let mut fields = Vec::new();
+    let mut bytes: u64 = 0;
+    let (_, end) = hash_range(key);
+    loop {
+        let page: Vec<KvPair> = reader.scan((start.clone()..end.clone()).into(), HASH_PAGE_SIZE).await?;
+        let count = page.len();
+        for p in page.into_iter() {
+            let field_key = Into::<Vec<u8>>::into(p.key().to_owned());
+            if let Some((value, _)) = live_field(Some(p.value()), now) {
+                let field = decode_hash_field(key, &field_key).to_vec();
+                bytes += (field.len() + value.len()) as u64;
+                if max_bytes > 0 && bytes > max_bytes && !fields.is_empty() {
+                    return Ok((fields, Some(field)));
+                }
+                fields.push((field, value));
+            }
+            start = field_key;
+            start.push(0);
+        }
+        if count < HASH_PAGE_SIZE as usize {
+            return Ok((fields, None));
+        }
+    }
+}
+
+// Every live field and value of the hash
+pub async fn read_fields(reader: &mut Reader<'_>, key: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+    Ok(fields_from(reader, key, hash_range(key).0, 0).await?.0)
+}
+
+// Flat [field, value, ...] array
+pub fn fields_reply(fields: Vec<(Vec<u8>, Vec<u8>)>) -> RedisValue {
+    let mut values: Vec<RedisValue> = Vec::new();
+    for (field, value) in fields.into_iter() {
+        values.push(field.into());
+        values.push(value.into());
+    }
+    values.into()
+}
+
+// Every field and value as a flat [field, value, ...] array. With
+// max-reply-size set, or when continuing from `cursor`, the fields come in
+// parts of about that many bytes, see paged_reply.
+pub async fn do_async_hgetall(key: &str, cursor: Option<Vec<u8>>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let max_bytes = get_config().max_reply_size;
+    let paged = max_bytes > 0 || cursor.is_some();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    if expect_type(&found, key, DataType::Hash)?.is_none() {
+        let empty = RedisValue::Array(vec![]);
+        return Ok(if paged { paged_reply(empty, None) } else { empty });
+    }
+    let start = match cursor {
+        Some(field) => encode_hash_key(key, &field),
+        None => hash_range(key).0,
+    };
+    let (fields, next) = fields_from(&mut Reader::Snapshot(&mut snapshot), key, start, max_bytes).await?;
+    let fields = fields_reply(fields);
+    Ok(if paged { paged_reply(fields, next) } else { fields })
+}
+
+// Fields at the given positions in field order, read page by page and
+// stopping after the last wanted position. Expired fields keep their
+// position but are left out.
+async fn fields_at(snapshot: &mut Snapshot, key: &[u8], positions: &BTreeSet<u64>) -> Result<HashMap<u64, (Vec<u8>, Vec<u8>)>, Error> {
+    let mut picked = HashMap::new();
+    let last = match positions.iter().next_back() {
+        Some(last) => *last,
+        None => return Ok(picked),
+    };
+    let now = now_millis();
+    let (mut start, end) = hash_range(key);
+    let mut index: u64 = 0;
+    loop {
+        let page: Vec<KvPair> = Reader::Snapshot(snapshot).scan((start.clone()..end.clone()).into(), HASH_PAGE_SIZE).await?;
+        let count = page.len();
+        for p in page.into_iter() {
+            let field_key = Into::<Vec<u8>>::into(p.key().to_owned());
+            if positions.contains(&index) {
+                if let Some((value, _)) = live_field(Some(p.value()), now) {
+                    picked.insert(index, (decode_hash_field(key, &field_key).to_vec(), value));
+                }
+            }
+            if index == last {
+                return Ok(picked);
+            }
+            index += 1;
+            start = field_key;
+            start.push(0);
+        }
+        if count < HASH_PAGE_SIZE as usize {
+            return Ok(picked);
+        }
+    }
+}
+
+// Random fields the way Redis HRANDFIELD picks them: a positive count gives
+// distinct fields, a negative one may repeat them. The field count from the
+// meta entry lets us draw positions up front, so only the picked fields are
+// kept and the scan ends at the last picked position.
+pub async fn do_async_hrandfield(key: &str, count: Option<i64>, with_values: bool, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let size = expect_type(&found, key, DataType::Hash)?.map_or(0, |m| m.size);
+    let empty = match count {
+        Some(_) => RedisValue::Array(vec![]),
+        None => RedisValue::Null,
+    };
+    if size == 0 || count == Some(0) {
+        return Ok(empty);
+    }
+
+    let want = count.unwrap_or(1);
+    let mut draws: Vec<u64> = Vec::new();
+    if want > 0 && want as u64 >= size {
+        draws.extend(0..size);
+    } else if want > 0 {
+        // Floyd's algorithm for `want` distinct positions
+        let mut chosen: HashSet<u64> = HashSet::new();
+        for j in size - want as u64..size {
+            let t = random_below(j + 1);
+            let pos = if chosen.contains(&t) { j } else { t };
+            chosen.insert(pos);
+            draws.push(pos);
+        }
+    } else {
+        draws.extend((0..want.unsigned_abs()).map(|_| random_below(size)));
+    }
+    // Reply order must not follow field order
+    shuffle(&mut draws);
+
+    let positions: BTreeSet<u64> = draws.iter().cloned().collect();
+    let picked = fields_at(&mut snapshot, key, &positions).await?;
+    let mut values: Vec<RedisValue> = Vec::new();
+    for pos in draws.iter() {
+        if let Some((field, value)) = picked.get(pos) {
+            values.push(field.clone().into());
+            if with_values {
+                values.push(value.clone().into());
+            }
+        }
+    }
+    if count.is_none() {
+        return Ok(values.into_iter().next().unwrap_or(RedisValue::Null));
+    }
+    Ok(RedisValue::Array(values))
+}
+
+// Condition under which HEXPIRE replaces a field's expiration
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExpireCondition {
+    Always,
+    // NX: the field has no expiration
+    NotSet,
+    // XX: the field has an expiration
+    IsSet,
+    // GT: the new expiration is later, no expiration counts as infinite
+    Greater,
+    // LT: the new expiration is earlier, no expiration counts as infinite
+    Less,
+}
+
+impl ExpireCondition {
+    fn allows(self, current: u64, expire_at: u64) -> bool {
+        match self {
+            ExpireCondition::Always => true,
+            ExpireCondition::NotSet => current == 0,
+            ExpireCondition::IsSet => current != 0,
+            ExpireCondition::Greater => current != 0 && expire_at > current,
+            ExpireCondition::Less => current == 0 || expire_at < current,
+        }
+    }
+}
+
+async fn hexpire(txn: &mut Transaction, key: &[u8], expire_at: u64, condition: ExpireCondition, fields: Vec<Vec<u8>>) -> Result<Vec<i64>, Error> {
+    let field_keys: Vec<Vec<u8>> = fields.iter().map(|field| encode_hash_key(key, field)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(field_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let mut meta = match expect_type(&found, key, DataType::Hash)? {
+        Some(meta) => meta,
+        None => return Ok(vec![-2; field_keys.len()]),
+    };
+
+    let now = now_millis();
+    let mut deleted: HashSet<Vec<u8>> = HashSet::new();
+    let mut replies = Vec::with_capacity(field_keys.len());
+    for field_key in field_keys.into_iter() {
+        let (value, current) = match live_field(found.get(&field_key), now) {
+            Some(field) if !deleted.contains(&field_key) => field,
+            _ => {
+                replies.push(-2);
+                continue;
+            },
+        };
+        if !condition.allows(current, expire_at) {
+            replies.push(0);
+            continue;
+        }
+        // A deadline already past deletes the field right away
+        if expire_at <= now {
+            txn.delete(field_key.clone()).await?;
+            deleted.insert(field_key);
+            replies.push(2);
+            continue;
+        }
+        txn.put(encode_field_ttl_key(&field_key), expire_at.to_be_bytes().to_vec()).await?;
+        txn.put(field_key, encode_field_value(&value, expire_at)).await?;
+        replies.push(1);
+    }
+    if !deleted.is_empty() {
+        meta.size = meta.size.saturating_sub(deleted.len() as u64);
+        if meta.size == 0 {
+            txn.delete(encode_meta_key(key)).await?;
+        } else {
+            txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+        }
+    }
+    Ok(replies)
+}
+
+// Set the expiration (Unix ms) of each field, replies per field: -2 no such
+// field, 0 condition not met, 1 expiration set, 2 field deleted because the
+// expiration is already past
+pub async fn do_async_hexpire(key: &str, expire_at: u64, condition: ExpireCondition, fields: Vec<String>) -> Result<RedisValue, Error> {
+    let fields = fields.into_iter().map(|f| f.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = hexpire(&mut txn, key.as_bytes(), expire_at, condition, fields).await;
+    let replies = finish_txn(txn, res).await?;
+    Ok(replies.into_iter().map(RedisValue::Integer).collect::<Vec<_>>().into())
+}
+
+// Remaining time to live of each field in seconds, -2 for a missing field
+// and -1 for a field without expiration
+pub async fn do_async_httl(key: &str, fields: Vec<String>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let field_keys: Vec<Vec<u8>> = fields.iter().map(|field| encode_hash_key(key, field.as_bytes())).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(field_keys.iter().cloned());
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    let exists = expect_type(&found, key, DataType::Hash)?.is_some();
+    let now = now_millis();
+    let ttls: Vec<RedisValue> = field_keys.iter().map(|k| {
+        let ttl = match live_field(found.get(k), now) {
+            Some((_, expire_at)) if exists && expire_at != 0 => ((expire_at - now + 500) / 1000) as i64,
+            Some(_) if exists => -1,
+            _ => -2,
+        };
+        RedisValue::Integer(ttl)
+    }).collect();
+    Ok(ttls.into())
+}
+
+// One page of the field TTL index: delete the fields that are due and
+// shrink their hashes' meta entries. Index entries that are due go away
+// too, a field written or deleted since then has nothing left to purge.
+async fn purge_fields_page(txn: &mut Transaction, start: Vec<u8>, end: Vec<u8>) -> Result<(u64, Option<Vec<u8>>), Error> {
+    let now = now_millis();
+    let entries = Reader::Txn(txn).scan((start..end).into(), HASH_PAGE_SIZE).await?;
+    let due: Vec<Vec<u8>> = entries.iter()
+        .filter(|p| decode_field_ttl(p.value()).is_some_and(|expire_at| expire_at <= now))
+        .map(|p| Into::<Vec<u8>>::into(p.key().to_owned()))
+        .collect();
+    let field_keys: Vec<Vec<u8>> = due.iter().map(|k| decode_field_ttl_key(k)).collect();
+    let found = Reader::Txn(txn).batch_get(field_keys.clone()).await?;
+
+    let mut shrink: HashMap<Vec<u8>, u64> = HashMap::new();
+    for (ttl_key, field_key) in due.into_iter().zip(field_keys) {
+        txn.delete(ttl_key).await?;
+        let expired = match found.get(&field_key).and_then(|raw| decode_field_value(raw)) {
+            Some((_, expire_at)) => expire_at != 0 && expire_at <= now,
+            None => false,
+        };
+        if !expired {
+            continue;
+        }
+        if let Some((key, _)) = split_hash_key(&field_key) {
+            *shrink.entry(key.to_vec()).or_default() += 1;
+        }
+        txn.delete(field_key).await?;
+    }
+
+    let purged = shrink.values().sum();
+    let meta_keys: Vec<Vec<u8>> = shrink.keys().map(|key| encode_meta_key(key)).collect();
+    let metas = Reader::Txn(txn).batch_get(meta_keys).await?;
+    for (key, removed) in shrink.into_iter() {
+        let meta_key = encode_meta_key(&key);
+        let mut meta = match metas.get(&meta_key).and_then(|raw| decode_meta(raw)) {
+            Some(meta) if meta.data_type == DataType::Hash => meta,
+            _ => continue,
+        };
+        meta.size = meta.size.saturating_sub(removed);
+        if meta.size == 0 {
+            txn.delete(meta_key).await?;
+        } else {
+            txn.put(meta_key, encode_meta(&meta)).await?;
+        }
+    }
+
+    let next = match entries.last() {
+        Some(last) if entries.len() == HASH_PAGE_SIZE as usize => {
+            let mut next = Into::<Vec<u8>>::into(last.key().to_owned());
+            next.push(0);
+            Some(next)
+        },
+        _ => None,
+    };
+    Ok((purged, next))
+}
+
+// Delete hash fields whose expiration passed, walking the field TTL index
+// one transaction per page. Returns how many fields were deleted.
+pub async fn do_async_purge_expired_fields() -> Result<u64, Error> {
+    let (mut start, end) = field_ttl_range();
+    let mut purged = 0;
+    loop {
+        let mut txn = begin_txn().await?;
+        let res = purge_fields_page(&mut txn, start.clone(), end.clone()).await;
+        let (count, next) = finish_txn(txn, res).await?;
+        purged += count;
+        match next {
+            Some(next) => start = next,
+            None => return Ok(purged),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeSet;
+use redis_module::{ RedisValue };
+use tikv_client::{ Error, Snapshot, Transaction };
+use crate::encoding::*;
+use crate::tikv::{ ReadBackend, clear_key, get_snapshot, get_txn_client, key_page, live_string, live_type, type_keys };
+use crate::txn::{ Reader, begin_txn, finish_txn };
+use crate::utils::now_millis;
+use crate::blocking::wake_key;
+
+// TiKV timestamps are a physical time in ms shifted left by the bits of a
+// logical counter, so a Unix time in ms maps to the first version of that
+// millisecond and back.
+const TSO_LOGICAL_BITS: u32 = 18;
+
+// Defaults of tikv.history: TiKV's default GC life time, older versions
+// may be gone, and the number of timestamps read across it
+pub const HISTORY_WINDOW_MS: u64 = 600_000;
+pub const HISTORY_SAMPLES: u64 = 60;
+pub const HISTORY_MAX_SAMPLES: u64 = 1000;
+
+pub fn version_at(ms: u64) -> u64 {
+    ms << TSO_LOGICAL_BITS
+}
+
+fn version_ms(version: u64) -> u64 {
+    version >> TSO_LOGICAL_BITS
+}
+
+// Value of a string key as of a timestamp version, expiration judged at
+// that time too. None when it was missing or held another type.
+async fn value_at(key: &[u8], version: u64) -> Result<Option<Vec<u8>>, Error> {
+    let mut snapshot = get_snapshot(ReadBackend::Pinned(version)).await?;
+    let mut found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let meta = found.remove(&encode_meta_key(key));
+    let data = found.remove(key);
+    Ok(live_string(data, meta, version_ms(version)).ok().and_then(|(value, _)| value))
+}
+
+// Versions of a string key over the last `window_ms`, read at `samples`
+// evenly spaced timestamps. Between two samples that differ, the first
+// version with another value is found by bisection down to the
+// millisecond, and again from there until the later sample is reached.
+// A value that was written and overwritten again between two reads is not
+// seen, and reads older than TiKV's GC safe point fail.
+async fn history(key: &[u8], window_ms: u64, samples: u64) -> Result<Vec<(u64, Option<Vec<u8>>)>, Error> {
+    let now = get_txn_client()?.current_timestamp().await?.version();
+    let since = version_at(version_ms(now).saturating_sub(window_ms));
+    let step = ((now - since) / samples).max(1);
+    let mut lo = since;
+    let mut lo_value = value_at(key, lo).await?;
+    let mut versions = vec![(version_ms(lo), lo_value.clone())];
+    let mut point = since;
+    while point < now {
+        point = (point + step).min(now);
+        let point_value = value_at(key, point).await?;
+        while lo_value != point_value {
+            let (mut a, mut b, mut b_value) = (lo, point, point_value.clone());
+            while b - a > version_at(1) {
+                let mid = a + (b - a) / 2;
+                let value = value_at(key, mid).await?;
+                if value == lo_value {
+                    a = mid;
+                } else {
+                    b = mid;
+                    b_value = value;
+                }
+            }
+            versions.push((version_ms(b), b_value.clone()));
+            lo = b;
+            lo_value = b_value;
+        }
+        lo = point;
+    }
+    Ok(versions)
+}
+
+// [[unix_ms, value], ...] newest first, the last entry being the value the
+// window started with
+pub async fn do_async_history(key: &str, window_ms: u64, samples: u64) -> Result<RedisValue, Error> {
+    let versions = history(key.as_bytes(), window_ms, samples).await?;
+    Ok(RedisValue::Array(versions.into_iter().rev().map(|(ms, value)| {
+        RedisValue::Array(vec![(ms as i64).into(), value.into()])
+    }).collect()))
+}
+
+const FLASHBACK_PAGE_SIZE: u32 = 1000;
+
+// Entries a key of `data_type` keeps besides its meta entry and the entry
+// under the key itself
+fn data_ranges(key: &[u8], data_type: DataType) -> Vec<(Vec<u8>, Vec<u8>)> {
+    match data_type {
+        DataType::String => vec![],
+        DataType::Hash => {
+            let (start, end) = hash_range(key);
+            vec![(encode_field_ttl_key(&start), encode_field_ttl_key(&end)), (start, end)]
+        },
+        DataType::List => vec![list_range(key)],
+        DataType::Set => vec![set_range(key)],
+        DataType::ZSet => vec![zset_member_range(key), zset_score_range(key)],
+    }
+}
+
+// Replace what `key` holds now with what it held in `snapshot`, taken at
+// Unix time `ms`: the current value of any type is cleared and every entry
+// the key had then is written back unchanged, expirations included.
+// Whether the key existed then.
+async fn flashback_key(txn: &mut Transaction, snapshot: &mut Snapshot, key: &[u8], ms: u64) -> Result<bool, Error> {
+    let mut found = Reader::Snapshot(&mut *snapshot).batch_get(type_keys(key)).await?;
+    clear_key(txn, key).await?;
+    let meta_key = encode_meta_key(key);
+    let meta = found.remove(&meta_key);
+    let data = found.remove(key);
+    let data_type = match live_type(data.is_some(), meta.as_ref(), ms) {
+        Some(data_type) => data_type,
+        None => return Ok(false),
+    };
+    if let (DataType::String, Some(data)) = (data_type, data) {
+        txn.put(key.to_vec(), data).await?;
+    }
+    if let Some(meta) = meta {
+        txn.put(meta_key, meta).await?;
+    }
+    for (mut start, end) in data_ranges(key, data_type) {
+        loop {
+            let page = Reader::Snapshot(&mut *snapshot).scan((start.clone()..end.clone()).into(), FLASHBACK_PAGE_SIZE).await?;
+            let count = page.len();
+            for p in page.into_iter() {
+                start = Into::<Vec<u8>>::into(p.key().to_owned());
+                txn.put(start.clone(), p.into_value()).await?;
+                start.push(0);
+            }
+            if count < FLASHBACK_PAGE_SIZE as usize {
+                break;
+            }
+        }
+    }
+    Ok(true)
+}
+
+// Keys of every type starting with `prefix`, expired ones included
+async fn prefix_keys(reader: &mut Reader<'_>, prefix: &[u8]) -> Result<BTreeSet<Vec<u8>>, Error> {
+    let end = prefix_end(prefix);
+    let mut keys = BTreeSet::new();
+    let mut start = prefix.to_vec();
+    loop {
+        let (page, next) = key_page(reader, &start, end.as_deref(), FLASHBACK_PAGE_SIZE).await?;
+        keys.extend(page.into_iter().map(|(key, _, _)| key));
+        match next {
+            Some(next) => start = next,
+            None => return Ok(keys),
+        }
+    }
+}
+
+async fn flashback(txn: &mut Transaction, key: &[u8], prefix: bool, ms: u64) -> Result<(i64, BTreeSet<Vec<u8>>), Error> {
+    let mut snapshot = get_snapshot(ReadBackend::Pinned(version_at(ms))).await?;
+    let keys = if prefix {
+        let mut keys = prefix_keys(&mut Reader::Snapshot(&mut snapshot), key).await?;
+        keys.extend(prefix_keys(&mut Reader::Txn(&mut *txn), key).await?);
+        keys
+    } else {
+        vec![key.to_vec()].into_iter().collect()
+    };
+    let mut restored = 0;
+    for key in keys.iter() {
+        if flashback_key(txn, &mut snapshot, key, ms).await? {
+            restored += 1;
+        }
+    }
+    Ok((restored, keys))
+}
+
+// Bring a key, or every key starting with a prefix, back to how it was at
+// Unix time `ms` in one transaction: keys created since are deleted, the
+// others rewritten with their old value as the current version. Replies
+// how many keys existed at that time. The whole prefix goes in a single
+// transaction, bounded by TiKV's transaction size limit.
+pub async fn do_async_flashback(key: &str, prefix: bool, ms: u64) -> Result<RedisValue, Error> {
+    if ms > now_millis() {
+        return Err(tikv_client::Error::StringError(String::from("ERR timestamp is in the future")));
+    }
+    let mut txn = begin_txn().await?;
+    let res = flashback(&mut txn, key.as_bytes(), prefix, ms).await;
+    let (restored, keys) = finish_txn(txn, res).await?;
+    keys.iter().for_each(|key| wake_key(key));
+    Ok(restored.into())
+}
@@ -1,9 +1,17 @@
 use std::thread;
+use std::os::raw::c_void;
 use tokio::time::{sleep, Duration};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, RwLock, Mutex};
-use tikv_client::RawClient;
-use redis_module::{Context, RedisString, Status };
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use tikv_client::{RawClient, TransactionClient};
+use redis_module::{raw, Context, RedisString, Status, ThreadSafeContext };
 use tokio::runtime::{ Runtime, Handle };
+use tokio::task::JoinHandle;
+use crate::tikv::{ ReadBackend, forget_client, do_async_purge_trash, do_async_purge_expired };
+use crate::hash::do_async_purge_expired_fields;
+use crate::config::load_config;
+use crate::blocking::Parked;
 
 lazy_static! {
     pub static ref GLOBAL_RT1: Arc<RwLock<Option<Box<Handle>>>> = Arc::new(RwLock::new(None));
@@ -11,11 +19,60 @@ lazy_static! {
     pub static ref GLOBAL_COUNTER: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
 
     pub static ref GLOBAL_CLIENT: Arc<RwLock<Option<Box<RawClient>>>> = Arc::new(RwLock::new(None));
+    pub static ref GLOBAL_PD_ADDRS: Arc<RwLock<Vec<String>>> = Arc::new(RwLock::new(Vec::new()));
+    pub static ref GLOBAL_TXN_CLIENT: Arc<RwLock<Option<Box<TransactionClient>>>> = Arc::new(RwLock::new(None));
+    pub static ref GLOBAL_READ_BACKEND: Arc<RwLock<HashMap<u64, ReadBackend>>> = Arc::new(RwLock::new(HashMap::new()));
+    pub static ref GLOBAL_READ_SNAPSHOT: Arc<RwLock<HashMap<u64, u64>>> = Arc::new(RwLock::new(HashMap::new()));
+    pub static ref GLOBAL_SCAN_SNAPSHOTS: Arc<RwLock<HashMap<u64, (u64, u64)>>> = Arc::new(RwLock::new(HashMap::new()));
+    pub static ref GLOBAL_WAITING_POPS: Arc<RwLock<HashSet<u64>>> = Arc::new(RwLock::new(HashSet::new()));
+    pub static ref GLOBAL_BLOCKED: Arc<Mutex<HashMap<Vec<u8>, VecDeque<Parked>>>> = Arc::new(Mutex::new(HashMap::new()));
+    pub static ref GLOBAL_CLIENT_TASKS: Arc<Mutex<HashMap<u64, HashMap<u64, JoinHandle<()>>>>> = Arc::new(Mutex::new(HashMap::new()));
     static ref GLOBAL_RUNNING: Arc<RwLock<u32>> = Arc::new(RwLock::new(1));
 }
 
+// Number of spawned TiKV tasks that have not finished yet
+pub static GLOBAL_INFLIGHT: AtomicUsize = AtomicUsize::new(0);
+// Set while tikv.switchcluster drains traffic and reconnects
+pub static GLOBAL_SWITCHING: AtomicBool = AtomicBool::new(false);
+
+// Seconds between two background purges of the trash namespace
+const TRASH_PURGE_INTERVAL: u64 = 60;
+// Seconds between two background purges of expired keys and hash fields
+const EXPIRE_PURGE_INTERVAL: u64 = 10;
+
+// Report a background purge in the Redis log, the purges run outside any
+// command so they log through a detached context
+fn log_purged(purged: u64, what: &str) {
+    if purged > 0 {
+        let ctx = ThreadSafeContext::new();
+        ctx.lock().log_debug(&format!("tikv: purged {} {}", purged, what));
+    }
+}
+
+// Server event callback, clears what the module keeps per connection once
+// the client is gone
+unsafe extern "C" fn on_client_change(_ctx: *mut raw::RedisModuleCtx, _eid: raw::RedisModuleEvent, subevent: u64, data: *mut c_void) {
+    if subevent != raw::REDISMODULE_SUBEVENT_CLIENT_CHANGE_DISCONNECTED as u64 || data.is_null() {
+        return;
+    }
+    let info = &*(data as *const raw::RedisModuleClientInfoV1);
+    forget_client(info.id);
+}
+
 // Initial tokio main executor in other thread
-pub fn tikv_init(_ctx: &Context, _args: &Vec<RedisString>) -> Status {
+pub fn tikv_init(ctx: &Context, args: &Vec<RedisString>) -> Status {
+    if let Err(err) = load_config(args) {
+        ctx.log_warning(&format!("tikv: {}", err));
+        return Status::Err;
+    }
+
+    let client_change = raw::RedisModuleEvent { id: raw::REDISMODULE_EVENT_CLIENT_CHANGE as u64, dataver: 1 };
+    let subscribed: Status = unsafe { raw::RedisModule_SubscribeToServerEvent.unwrap()(ctx.ctx, client_change, Some(on_client_change)) }.into();
+    if subscribed == Status::Err {
+        ctx.log_warning("tikv: cannot subscribe to client change events");
+        return Status::Err;
+    }
+
     thread::spawn(move || {
         let runtime = Runtime::new().unwrap();
         let handle = runtime.handle().clone();
@@ -23,11 +80,30 @@ pub fn tikv_init(_ctx: &Context, _args: &Vec<RedisString>) -> Status {
         *GLOBAL_RUNNING.write().unwrap() = 1;
         println!("Tokio Runtime 1 Created!");
         runtime.block_on(async {
+            let mut ticks: u64 = 0;
             loop {
                 sleep(Duration::from_secs(1)).await;
                 if *GLOBAL_RUNNING.read().unwrap() == 0 {
                     return;
                 }
+                ticks += 1;
+                if ticks % TRASH_PURGE_INTERVAL == 0 {
+                    tokio::spawn(async {
+                        if let Ok(purged) = do_async_purge_trash().await {
+                            log_purged(purged, "keys from trash");
+                        }
+                    });
+                }
+                if ticks % EXPIRE_PURGE_INTERVAL == 0 {
+                    tokio::spawn(async {
+                        if let Ok(purged) = do_async_purge_expired().await {
+                            log_purged(purged, "expired keys");
+                        }
+                        if let Ok(purged) = do_async_purge_expired_fields().await {
+                            log_purged(purged, "expired hash fields");
+                        }
+                    });
+                }
             }
         });
         println!("Tokio Runtime 1 Finished");
@@ -6,16 +6,30 @@ extern crate lazy_static;
 extern crate tokio;
 extern crate reqwest;
 extern crate tikv_client;
+extern crate serde_json;
 
 mod init;
 mod utils;
 mod commands;
 mod tikv;
 mod pd;
+mod config;
+mod encoding;
+mod retry;
+mod txn;
+mod numeric;
+mod hash;
+mod list;
+mod set;
+mod zset;
+mod sort;
+mod history;
+mod blocking;
 
 use init::{ tikv_init, tikv_deinit };
 use commands::*;
 use pd::*;
+use config::{ tikv_config, tikv_readonly };
 
 // register functions
 redis_module! {
@@ -27,16 +41,119 @@ redis_module! {
     commands: [
         ["tikv.conn", tikv_connect, "", 0, 0, 0],
         ["tikv.get", tikv_get, "", 0, 0, 0],
+        ["tikv.strlen", tikv_strlen, "", 0, 0, 0],
+        ["tikv.getrange", tikv_getrange, "", 0, 0, 0],
         ["tikv.put", tikv_put, "", 0, 0, 0],
         ["tikv.set", tikv_put, "", 0, 0, 0],
+        ["tikv.setnx", tikv_setnx, "", 0, 0, 0],
+        ["tikv.setex", tikv_setex, "", 0, 0, 0],
+        ["tikv.psetex", tikv_setex, "", 0, 0, 0],
+        ["tikv.getset", tikv_getset, "", 0, 0, 0],
+        ["tikv.getex", tikv_getex, "", 0, 0, 0],
+        ["tikv.append", tikv_append, "", 0, 0, 0],
+        ["tikv.incr", tikv_incr, "", 0, 0, 0],
+        ["tikv.decr", tikv_incr, "", 0, 0, 0],
+        ["tikv.incrby", tikv_incr_by, "", 0, 0, 0],
+        ["tikv.decrby", tikv_incr_by, "", 0, 0, 0],
+        ["tikv.incrbyfloat", tikv_incr_by_float, "", 0, 0, 0],
+        ["tikv.setrange", tikv_setrange, "", 0, 0, 0],
+        ["tikv.cas", tikv_cas, "", 0, 0, 0],
         ["tikv.del", tikv_del, "", 0, 0, 0],
+        ["tikv.restorekey", tikv_restore_key, "", 0, 0, 0],
         ["tikv.delrange", tikv_del_range, "", 0, 0, 0],
+        ["tikv.migrateraw", tikv_migrate_raw, "", 0, 0, 0],
         ["tikv.load", tikv_load, "", 0, 0, 0],
         ["tikv.scan", tikv_scan, "", 0, 0, 0],
+        ["tikv.scanvalues", tikv_scan_values, "", 0, 0, 0],
+        ["tikv.scankeys", tikv_scan_keys, "", 0, 0, 0],
+        ["tikv.ttlscan", tikv_ttl_scan, "", 0, 0, 0],
+        ["tikv.batchscan", tikv_batch_scan, "", 0, 0, 0],
+        ["tikv.count", tikv_count, "", 0, 0, 0],
         ["tikv.close", tikv_close, "", 0, 0, 0],
+        ["tikv.switchcluster", tikv_switch_cluster, "", 0, 0, 0],
+        ["tikv.pool", tikv_pool, "", 0, 0, 0],
+        ["tikv.readmode", tikv_read_mode, "", 0, 0, 0],
+        ["tikv.readsnapshot", tikv_read_snapshot, "", 0, 0, 0],
+        ["tikv.config", tikv_config, "", 0, 0, 0],
+        ["tikv.readonly", tikv_readonly, "", 0, 0, 0],
         ["tikv.mget", tikv_batch_get, "", 0, 0, 0],
+        ["tikv.mgetany", tikv_mget_any, "", 0, 0, 0],
         ["tikv.mput", tikv_batch_put, "", 0, 0, 0],
         ["tikv.mset", tikv_batch_put, "", 0, 0, 0],
+        ["tikv.msetnx", tikv_batch_put, "", 0, 0, 0],
+        ["tikv.bitpos", tikv_bitpos, "", 0, 0, 0],
+        ["tikv.lcs", tikv_lcs, "", 0, 0, 0],
+        ["tikv.waitkey", tikv_wait_key, "", 0, 0, 0],
+        ["tikv.history", tikv_history, "", 0, 0, 0],
+        ["tikv.flashback", tikv_flashback, "", 0, 0, 0],
+        ["tikv.hset", tikv_hset, "", 0, 0, 0],
+        ["tikv.hsetnx", tikv_hsetnx, "", 0, 0, 0],
+        ["tikv.hget", tikv_hget, "", 0, 0, 0],
+        ["tikv.hstrlen", tikv_hstrlen, "", 0, 0, 0],
+        ["tikv.hdel", tikv_hdel, "", 0, 0, 0],
+        ["tikv.hbatchdel", tikv_hbatchdel, "", 0, 0, 0],
+        ["tikv.hlen", tikv_hlen, "", 0, 0, 0],
+        ["tikv.hrandfield", tikv_hrandfield, "", 0, 0, 0],
+        ["tikv.hmget", tikv_hmget, "", 0, 0, 0],
+        ["tikv.hmgetmulti", tikv_hmget_multi, "", 0, 0, 0],
+        ["tikv.hgetall", tikv_hgetall, "", 0, 0, 0],
+        ["tikv.hincrby", tikv_hincr_by, "", 0, 0, 0],
+        ["tikv.hincrbyfloat", tikv_hincr_by_float, "", 0, 0, 0],
+        ["tikv.hexpire", tikv_hexpire, "", 0, 0, 0],
+        ["tikv.hpexpire", tikv_hexpire, "", 0, 0, 0],
+        ["tikv.httl", tikv_httl, "", 0, 0, 0],
+        ["tikv.lpush", tikv_push, "", 0, 0, 0],
+        ["tikv.rpush", tikv_push, "", 0, 0, 0],
+        ["tikv.lpop", tikv_pop, "", 0, 0, 0],
+        ["tikv.rpop", tikv_pop, "", 0, 0, 0],
+        ["tikv.lrange", tikv_lrange, "", 0, 0, 0],
+        ["tikv.llen", tikv_llen, "", 0, 0, 0],
+        ["tikv.lindex", tikv_lindex, "", 0, 0, 0],
+        ["tikv.lset", tikv_lset, "", 0, 0, 0],
+        ["tikv.ltrim", tikv_ltrim, "", 0, 0, 0],
+        ["tikv.linsert", tikv_linsert, "", 0, 0, 0],
+        ["tikv.lmove", tikv_lmove, "", 0, 0, 0],
+        ["tikv.rpoplpush", tikv_lmove, "", 0, 0, 0],
+        ["tikv.blpop", tikv_blocking_pop, "", 0, 0, 0],
+        ["tikv.brpop", tikv_blocking_pop, "", 0, 0, 0],
+        ["tikv.lpos", tikv_lpos, "", 0, 0, 0],
+        ["tikv.sadd", tikv_sadd, "", 0, 0, 0],
+        ["tikv.srem", tikv_srem, "", 0, 0, 0],
+        ["tikv.smembers", tikv_smembers, "", 0, 0, 0],
+        ["tikv.scard", tikv_scard, "", 0, 0, 0],
+        ["tikv.sismember", tikv_sismember, "", 0, 0, 0],
+        ["tikv.smismember", tikv_sismember, "", 0, 0, 0],
+        ["tikv.srandmember", tikv_srandmember, "", 0, 0, 0],
+        ["tikv.spop", tikv_spop, "", 0, 0, 0],
+        ["tikv.sinter", tikv_set_algebra, "", 0, 0, 0],
+        ["tikv.sunion", tikv_set_algebra, "", 0, 0, 0],
+        ["tikv.sdiff", tikv_set_algebra, "", 0, 0, 0],
+        ["tikv.sinterstore", tikv_set_algebra_store, "", 0, 0, 0],
+        ["tikv.sunionstore", tikv_set_algebra_store, "", 0, 0, 0],
+        ["tikv.sdiffstore", tikv_set_algebra_store, "", 0, 0, 0],
+        ["tikv.sintercard", tikv_sintercard, "", 0, 0, 0],
+        ["tikv.smove", tikv_smove, "", 0, 0, 0],
+        ["tikv.sscan", tikv_sscan, "", 0, 0, 0],
+        ["tikv.sort", tikv_sort, "", 0, 0, 0],
+        ["tikv.zadd", tikv_zadd, "", 0, 0, 0],
+        ["tikv.zscore", tikv_zscore, "", 0, 0, 0],
+        ["tikv.zcard", tikv_zcard, "", 0, 0, 0],
+        ["tikv.zrange", tikv_zrange, "", 0, 0, 0],
+        ["tikv.zrevrange", tikv_zrange, "", 0, 0, 0],
+        ["tikv.zrangebyscore", tikv_zrange_by_score, "", 0, 0, 0],
+        ["tikv.zrevrangebyscore", tikv_zrange_by_score, "", 0, 0, 0],
+        ["tikv.zrangebylex", tikv_zrange_by_lex, "", 0, 0, 0],
+        ["tikv.zrevrangebylex", tikv_zrange_by_lex, "", 0, 0, 0],
+        ["tikv.zrangestore", tikv_zrangestore, "", 0, 0, 0],
+        ["tikv.zrem", tikv_zrem, "", 0, 0, 0],
+        ["tikv.zremrangebyscore", tikv_zrem_range, "", 0, 0, 0],
+        ["tikv.zremrangebyrank", tikv_zrem_range, "", 0, 0, 0],
+        ["tikv.zincrby", tikv_zincrby, "", 0, 0, 0],
+        ["tikv.zrank", tikv_zrank, "", 0, 0, 0],
+        ["tikv.zrevrank", tikv_zrank, "", 0, 0, 0],
+        ["tikv.zpopmin", tikv_zpop, "", 0, 0, 0],
+        ["tikv.zpopmax", tikv_zpop, "", 0, 0, 0],
+        ["tikv.zscan", tikv_zscan, "", 0, 0, 0],
         ["pd.members", pd_members, "", 0, 0, 0],
     ],
 }
@@ -0,0 +1,485 @@
+use redis_module::{ RedisValue };
+use tikv_client::{ Error, Snapshot, Transaction };
+use crate::encoding::*;
+use crate::tikv::{ ReadBackend, get_snapshot, type_keys, expect_type };
+use crate::txn::{ Reader, begin_txn, delete_range, finish_txn };
+use crate::retry::{ with_retry, RetryClass };
+use crate::utils::index_window;
+use crate::blocking::{ park, still_waiting, stop_waiting, wait_deadline, wake_key };
+use tokio::time::Instant;
+
+// A list is a meta entry of type List, holding the element count and the
+// sequence number of the first element, plus one entry per element, see
+// encode_list_key. Elements always occupy head..head + size, so pushing on
+// the left takes head - 1 and pushing on the right head + size. Every
+// change rewrites the meta entry, so concurrent writers to the same list
+// conflict instead of taking the same sequence number.
+
+const LIST_PAGE_SIZE: u32 = 1000;
+
+// Offset of the element at a Redis style index, negative ones counting
+// from the end. None when out of range.
+fn list_offset(size: u64, index: i64) -> Option<u64> {
+    let offset = if index < 0 { size as i64 + index } else { index };
+    if offset < 0 || offset as u64 >= size {
+        return None;
+    }
+    Some(offset as u64)
+}
+
+// Values of the elements at sequence numbers first..=last, scanned page by
+// page so only the window is fetched
+async fn read_elements(mut reader: Reader<'_>, key: &[u8], first: u64, last: u64) -> Result<Vec<Vec<u8>>, Error> {
+    let mut values = Vec::new();
+    let mut start = encode_list_key(key, first);
+    let end = encode_list_key(key, last + 1);
+    loop {
+        let page = reader.scan((start.clone()..end.clone()).into(), LIST_PAGE_SIZE).await?;
+        let count = page.len();
+        for p in page.into_iter() {
+            start = Into::<Vec<u8>>::into(p.key().to_owned());
+            start.push(0);
+            values.push(p.into_value());
+        }
+        if count < LIST_PAGE_SIZE as usize {
+            return Ok(values);
+        }
+    }
+}
+
+// Every element of the list described by `meta`
+pub async fn read_list(reader: Reader<'_>, key: &[u8], meta: &KeyMeta) -> Result<Vec<Vec<u8>>, Error> {
+    if meta.size == 0 {
+        return Ok(Vec::new());
+    }
+    read_elements(reader, key, meta.head, meta.head + meta.size - 1).await
+}
+
+// Offset of the first element equal to `element`, scanning from the head
+// page by page and stopping at the first match
+async fn find_element(mut reader: Reader<'_>, key: &[u8], element: &[u8]) -> Result<Option<u64>, Error> {
+    let (mut start, end) = list_range(key);
+    let mut offset: u64 = 0;
+    loop {
+        let page = reader.scan((start.clone()..end.clone()).into(), LIST_PAGE_SIZE).await?;
+        let count = page.len();
+        for p in page.into_iter() {
+            if p.value().as_slice() == element {
+                return Ok(Some(offset));
+            }
+            offset += 1;
+            start = Into::<Vec<u8>>::into(p.key().to_owned());
+            start.push(0);
+        }
+        if count < LIST_PAGE_SIZE as usize {
+            return Ok(None);
+        }
+    }
+}
+
+async fn push(txn: &mut Transaction, key: &[u8], values: Vec<Vec<u8>>, left: bool) -> Result<i64, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = expect_type(&found, key, DataType::List)?.unwrap_or_else(|| KeyMeta::new(DataType::List, 0));
+    for value in values.into_iter() {
+        let seq = if left {
+            meta.head -= 1;
+            meta.head
+        } else {
+            meta.head + meta.size
+        };
+        txn.put(encode_list_key(key, seq), value).await?;
+        meta.size += 1;
+    }
+    txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    Ok(meta.size as i64)
+}
+
+// Push the values one after the other on the left (each becoming the new
+// head, like LPUSH) or on the right, replies the new length
+pub async fn do_async_push(key: &str, values: Vec<String>, left: bool) -> Result<RedisValue, Error> {
+    let values = values.into_iter().map(|v| v.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = push(&mut txn, key.as_bytes(), values, left).await;
+    let len = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(len.into())
+}
+
+// Store the meta entry of a list that changed, removing it with the last
+// element
+async fn save_meta(txn: &mut Transaction, key: &[u8], meta: &KeyMeta) -> Result<(), Error> {
+    if meta.size == 0 {
+        txn.delete(encode_meta_key(key)).await
+    } else {
+        txn.put(encode_meta_key(key), encode_meta(meta)).await
+    }
+}
+
+// Sequence numbers of up to `count` elements taken from one end, in the
+// order they are popped
+fn end_seqs(meta: &KeyMeta, count: u64, left: bool) -> Vec<u64> {
+    let count = count.min(meta.size);
+    if left {
+        (meta.head..meta.head + count).collect()
+    } else {
+        let tail = meta.head + meta.size;
+        (tail - count..tail).rev().collect()
+    }
+}
+
+async fn pop(txn: &mut Transaction, key: &[u8], count: u64, left: bool) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = match expect_type(&found, key, DataType::List)? {
+        Some(meta) => meta,
+        None => return Ok(None),
+    };
+    let element_keys: Vec<Vec<u8>> = end_seqs(&meta, count, left).into_iter().map(|seq| encode_list_key(key, seq)).collect();
+    let mut elements = Reader::Txn(txn).batch_get(element_keys.clone()).await?;
+    let mut values = Vec::with_capacity(element_keys.len());
+    for element_key in element_keys.iter() {
+        if let Some(value) = elements.remove(element_key) {
+            values.push(value);
+        }
+        txn.delete(element_key.clone()).await?;
+    }
+    if left {
+        meta.head += element_keys.len() as u64;
+    }
+    meta.size -= element_keys.len() as u64;
+    save_meta(txn, key, &meta).await?;
+    Ok(Some(values))
+}
+
+// Remove and return up to `count` elements from the left or the right end.
+// Without a count the reply is a single element, nil when the list does
+// not exist; with one it is an array, a nil array for a missing list.
+pub async fn do_async_pop(key: &str, count: Option<u64>, left: bool) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = pop(&mut txn, key.as_bytes(), count.unwrap_or(1), left).await;
+    let values = finish_txn(txn, res).await?;
+    Ok(match (values, count) {
+        (None, _) => RedisValue::Null,
+        (Some(values), Some(_)) => RedisValue::Array(values.into_iter().map(RedisValue::from).collect()),
+        (Some(values), None) => values.into_iter().next().map_or(RedisValue::Null, RedisValue::from),
+    })
+}
+
+// Elements between the start and stop indexes, both inclusive
+pub async fn do_async_lrange(key: &str, start: i64, stop: i64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let meta = match expect_type(&found, key, DataType::List)? {
+        Some(meta) => meta,
+        None => return Ok(RedisValue::Array(vec![])),
+    };
+    let values = match index_window(meta.size, start, stop) {
+        Some((first, last)) => read_elements(Reader::Snapshot(&mut snapshot), key, meta.head + first, meta.head + last).await?,
+        None => Vec::new(),
+    };
+    Ok(RedisValue::Array(values.into_iter().map(RedisValue::from).collect()))
+}
+
+// Element count from the meta entry, a single point read
+pub async fn do_async_llen(key: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let size = expect_type(&found, key, DataType::List)?.map_or(0, |m| m.size);
+    Ok((size as i64).into())
+}
+
+// Element at an index, computed from the head in the meta entry and read
+// with a point get
+pub async fn do_async_lindex(key: &str, index: i64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let meta = match expect_type(&found, key, DataType::List)? {
+        Some(meta) => meta,
+        None => return Ok(RedisValue::Null),
+    };
+    let offset = match list_offset(meta.size, index) {
+        Some(offset) => offset,
+        None => return Ok(RedisValue::Null),
+    };
+    let value = snapshot.get(encode_list_key(key, meta.head + offset)).await?;
+    Ok(value.into())
+}
+
+async fn lset(txn: &mut Transaction, key: &[u8], index: i64, value: Vec<u8>) -> Result<(), Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let meta = expect_type(&found, key, DataType::List)?.ok_or_else(|| {
+        tikv_client::Error::StringError(String::from("ERR no such key"))
+    })?;
+    let offset = list_offset(meta.size, index).ok_or_else(|| {
+        tikv_client::Error::StringError(String::from("ERR index out of range"))
+    })?;
+    // Rewriting the meta entry makes a concurrent pop or trim conflict
+    // with this write instead of leaving the element outside the list
+    txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    txn.put(encode_list_key(key, meta.head + offset), value).await
+}
+
+pub async fn do_async_lset(key: &str, index: i64, value: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = lset(&mut txn, key.as_bytes(), index, value.as_bytes().to_vec()).await;
+    finish_txn(txn, res).await?;
+    Ok("OK".into())
+}
+
+async fn ltrim(txn: &mut Transaction, key: &[u8], start: i64, stop: i64) -> Result<(), Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = match expect_type(&found, key, DataType::List)? {
+        Some(meta) => meta,
+        None => return Ok(()),
+    };
+    let tail = meta.head + meta.size;
+    let (first, last) = match index_window(meta.size, start, stop) {
+        Some((first, last)) => (meta.head + first, meta.head + last),
+        // Nothing is kept, the whole list goes
+        None => (tail, tail - 1),
+    };
+    delete_range(txn, (encode_list_key(key, meta.head), encode_list_key(key, first))).await?;
+    delete_range(txn, (encode_list_key(key, last + 1), encode_list_key(key, tail))).await?;
+    meta.head = first;
+    meta.size = last + 1 - first;
+    save_meta(txn, key, &meta).await
+}
+
+// Keep only the elements between the start and stop indexes
+pub async fn do_async_ltrim(key: &str, start: i64, stop: i64) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = ltrim(&mut txn, key.as_bytes(), start, stop).await;
+    finish_txn(txn, res).await?;
+    Ok("OK".into())
+}
+
+async fn linsert(txn: &mut Transaction, key: &[u8], before: bool, pivot: &[u8], value: Vec<u8>) -> Result<i64, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = match expect_type(&found, key, DataType::List)? {
+        Some(meta) => meta,
+        None => return Ok(0),
+    };
+    let pivot = match find_element(Reader::Txn(txn), key, pivot).await? {
+        Some(offset) => offset,
+        None => return Ok(-1),
+    };
+    // Offset the new element ends up at. Sequence numbers are dense, so
+    // the elements on one side of it move by one to make room: the side
+    // with fewer elements.
+    let at = if before { pivot } else { pivot + 1 };
+    if at < meta.size - at {
+        let moved = if at > 0 {
+            read_elements(Reader::Txn(txn), key, meta.head, meta.head + at - 1).await?
+        } else {
+            Vec::new()
+        };
+        for (i, element) in moved.into_iter().enumerate() {
+            txn.put(encode_list_key(key, meta.head - 1 + i as u64), element).await?;
+        }
+        meta.head -= 1;
+    } else {
+        let moved = read_elements(Reader::Txn(txn), key, meta.head + at, meta.head + meta.size - 1).await?;
+        for (i, element) in moved.into_iter().enumerate() {
+            txn.put(encode_list_key(key, meta.head + at + 1 + i as u64), element).await?;
+        }
+    }
+    txn.put(encode_list_key(key, meta.head + at), value).await?;
+    meta.size += 1;
+    txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    Ok(meta.size as i64)
+}
+
+// Insert an element before or after the first occurrence of `pivot`,
+// replies the new length, -1 when the pivot is missing and 0 when the list
+// is. Costs a scan up to the pivot plus rewriting the shorter side.
+pub async fn do_async_linsert(key: &str, before: bool, pivot: &str, value: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = linsert(&mut txn, key.as_bytes(), before, pivot.as_bytes(), value.as_bytes().to_vec()).await;
+    let len = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(len.into())
+}
+
+async fn lmove(txn: &mut Transaction, source: &[u8], destination: &[u8], from_left: bool, to_left: bool) -> Result<Option<Vec<u8>>, Error> {
+    // A destination of another type fails before anything is popped
+    let found = Reader::Txn(txn).batch_get(type_keys(destination)).await?;
+    expect_type(&found, destination, DataType::List)?;
+    let element = match pop(txn, source, 1, from_left).await? {
+        Some(mut values) if !values.is_empty() => values.remove(0),
+        _ => return Ok(None),
+    };
+    // The transaction reads its own writes, so moving within one list
+    // pushes onto the list as the pop left it
+    push(txn, destination, vec![element.clone()], to_left).await?;
+    Ok(Some(element))
+}
+
+// Pop an element from one end of `source` and push it onto one end of
+// `destination` in a single transaction, replies the element or nil when
+// the source is missing
+pub async fn do_async_lmove(source: &str, destination: &str, from_left: bool, to_left: bool) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = lmove(&mut txn, source.as_bytes(), destination.as_bytes(), from_left, to_left).await;
+    let element = finish_txn(txn, res).await?;
+    wake_key(destination.as_bytes());
+    Ok(element.into())
+}
+
+// First of `keys` holding a non-empty list, read from one snapshot
+async fn first_nonempty(keys: &[String]) -> Result<Option<&str>, Error> {
+    if keys.is_empty() {
+        return Ok(None);
+    }
+    let mut wanted = Vec::new();
+    for key in keys.iter() {
+        wanted.extend(type_keys(key.as_bytes()));
+    }
+    let mut snapshot = get_snapshot(ReadBackend::Txn).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    for key in keys.iter() {
+        if expect_type(&found, key.as_bytes(), DataType::List)?.is_some() {
+            return Ok(Some(key));
+        }
+    }
+    Ok(None)
+}
+
+async fn pop_one(key: &str, left: bool) -> Result<Option<Vec<u8>>, Error> {
+    let mut txn = begin_txn().await?;
+    let res = pop(&mut txn, key.as_bytes(), 1, left).await;
+    let values = finish_txn(txn, res).await?;
+    Ok(values.and_then(|values| values.into_iter().next()))
+}
+
+async fn blocking_pop(cid: u64, keys: &[String], left: bool, timeout_ms: u64) -> Result<RedisValue, Error> {
+    let deadline = wait_deadline(timeout_ms)?;
+    let waiter = park(cid, keys.iter().map(|k| k.as_bytes().to_vec()).collect(), true);
+    loop {
+        // Nobody is left to take the element once the client disconnected
+        if !still_waiting(cid) {
+            return Ok(RedisValue::Null);
+        }
+        // Clients waiting longer on a key pop from it first
+        let turns: Vec<String> = keys.iter().filter(|k| waiter.is_first(k.as_bytes())).cloned().collect();
+        if let Some(key) = first_nonempty(&turns).await? {
+            // Another client may empty the list first, then keep waiting
+            if !still_waiting(cid) {
+                return Ok(RedisValue::Null);
+            }
+            if let Some(element) = with_retry(RetryClass::NonIdempotent, || pop_one(key, left)).await? {
+                return Ok(RedisValue::Array(vec![key.into(), element.into()]));
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(RedisValue::Null);
+        }
+        waiter.wait(deadline).await;
+    }
+}
+
+// Pop from the first non-empty list among `keys` for client `cid`, parked
+// on them while they are all empty, see blocking.rs. Replies [key,
+// element], or nil once `timeout_ms` elapsed (0 waits up to
+// BLOCKING_MAX_WAIT) or the client disconnected. The caller registers
+// `cid` with start_waiting.
+pub async fn do_async_blocking_pop(cid: u64, keys: Vec<String>, left: bool, timeout_ms: u64) -> Result<RedisValue, Error> {
+    let res = blocking_pop(cid, &keys, left, timeout_ms).await;
+    stop_waiting(cid);
+    res
+}
+
+// Options of LPOS: which match to start from (negative ones counting from
+// the tail), how many matches to return (0 for all) and how many elements
+// to compare at most (0 for all)
+#[derive(Clone, Copy)]
+pub struct PosOptions {
+    pub rank: i64,
+    pub count: Option<u64>,
+    pub maxlen: u64,
+}
+
+async fn lpos(snapshot: &mut Snapshot, key: &[u8], meta: &KeyMeta, element: &[u8], opts: PosOptions) -> Result<Vec<u64>, Error> {
+    let mut matches = Vec::new();
+    let mut skip = opts.rank.unsigned_abs() - 1;
+    let wanted = match opts.count {
+        Some(0) => u64::MAX,
+        Some(count) => count,
+        None => 1,
+    };
+    let limit = if opts.maxlen == 0 { meta.size } else { opts.maxlen.min(meta.size) };
+    // Offsets are dense, so each page is a window of sequence numbers
+    // walked from the end the rank starts at
+    let mut scanned = 0;
+    while scanned < limit {
+        let n = (limit - scanned).min(LIST_PAGE_SIZE as u64);
+        let first = if opts.rank > 0 { scanned } else { meta.size - scanned - n };
+        let values = read_elements(Reader::Snapshot(snapshot), key, meta.head + first, meta.head + first + n - 1).await?;
+        let mut page: Vec<(u64, Vec<u8>)> = (first..).zip(values).collect();
+        if opts.rank < 0 {
+            page.reverse();
+        }
+        for (offset, value) in page.into_iter() {
+            if value != element {
+                continue;
+            }
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            matches.push(offset);
+            if matches.len() as u64 == wanted {
+                return Ok(matches);
+            }
+        }
+        scanned += n;
+    }
+    Ok(matches)
+}
+
+// Indexes of the elements equal to `element`. Without COUNT the reply is
+// the first match or nil, with it an array. The list is read one page at
+// a time and never loaded whole.
+pub async fn do_async_lpos(key: &str, element: &str, opts: PosOptions, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let matches = match expect_type(&found, key, DataType::List)? {
+        Some(meta) => lpos(&mut snapshot, key, &meta, element.as_bytes(), opts).await?,
+        None => Vec::new(),
+    };
+    let mut matches = matches.into_iter().map(|offset| RedisValue::Integer(offset as i64));
+    Ok(match opts.count {
+        Some(_) => RedisValue::Array(matches.collect()),
+        None => matches.next().unwrap_or(RedisValue::Null),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_offset_from_both_ends() {
+        assert_eq!(list_offset(3, 0), Some(0));
+        assert_eq!(list_offset(3, 2), Some(2));
+        assert_eq!(list_offset(3, 3), None);
+        assert_eq!(list_offset(3, -1), Some(2));
+        assert_eq!(list_offset(3, -3), Some(0));
+        assert_eq!(list_offset(3, -4), None);
+        assert_eq!(list_offset(0, 0), None);
+    }
+
+    #[test]
+    fn end_seqs_start_at_the_popped_end() {
+        let mut meta = KeyMeta::new(DataType::List, 0);
+        meta.size = 3;
+        let head = meta.head;
+        assert_eq!(end_seqs(&meta, 2, true), vec![head, head + 1]);
+        assert_eq!(end_seqs(&meta, 2, false), vec![head + 2, head + 1]);
+        assert_eq!(end_seqs(&meta, 10, true), vec![head, head + 1, head + 2]);
+        meta.size = 0;
+        assert!(end_seqs(&meta, 1, false).is_empty());
+    }
+}
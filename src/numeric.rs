@@ -0,0 +1,56 @@
+use redis_module::{ RedisValue };
+use tikv_client::Error;
+use crate::tikv::modify_string;
+use crate::txn::{ begin_txn, finish_txn };
+use crate::blocking::wake_key;
+
+// Parse a stored or given float the way Redis does: no surrounding spaces
+// and no NaN or infinity
+pub fn parse_float(raw: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(raw).ok()?;
+    if text.is_empty() || text.trim() != text {
+        return None;
+    }
+    text.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+// Parse a sorted set score: a float like parse_float, or an infinity
+// spelled inf, +inf or -inf in any case
+pub fn parse_score(raw: &[u8]) -> Option<f64> {
+    match std::str::from_utf8(raw).ok()?.to_lowercase().as_str() {
+        "inf" | "+inf" => Some(f64::INFINITY),
+        "-inf" => Some(f64::NEG_INFINITY),
+        _ => parse_float(raw),
+    }
+}
+
+// Shortest plain decimal that parses back to the same f64, never with an
+// exponent. Redis computes INCRBYFLOAT in long double and prints 17
+// digits, so the last digits can differ: 0.1 + 0.2 gives
+// 0.30000000000000004 here and 0.3 in Redis.
+pub fn format_float(value: f64) -> String {
+    format!("{}", value)
+}
+
+// New value of a float counter, `old` being the current one (0 when
+// missing). `not_float` is the error reported when `old` is not a float.
+pub fn incr_float(old: Option<Vec<u8>>, delta: f64, not_float: &str) -> Result<(Vec<u8>, String), Error> {
+    let current = match old {
+        Some(raw) => parse_float(&raw).ok_or_else(|| tikv_client::Error::StringError(not_float.to_owned()))?,
+        None => 0.0,
+    };
+    let value = current + delta;
+    if !value.is_finite() {
+        return Err(tikv_client::Error::StringError(String::from("ERR increment would produce NaN or Infinity")));
+    }
+    let text = format_float(value);
+    Ok((text.clone().into_bytes(), text))
+}
+
+pub async fn do_async_incr_by_float(key: &str, delta: f64) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = modify_string(&mut txn, key.as_bytes(), |old| incr_float(old, delta, "ERR value is not a valid float")).await;
+    let value = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(value.into())
+}
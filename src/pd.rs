@@ -1,6 +1,8 @@
 use reqwest::{ Client, Error };
 use redis_module::{Context, NextArg, RedisResult, RedisValue, RedisString };
-use crate::utils::{ redis_resp, tokio_spawn };
+use crate::utils::{ redis_resp, to_hex, tokio_spawn };
+use crate::encoding::{ encode_memcomparable, prefix_end, user_range_start };
+use crate::init::GLOBAL_PD_ADDRS;
 
 pub async fn do_async_curl(url: &str) -> Result<RedisValue, Error> {
     let client = Client::new();
@@ -14,6 +16,43 @@ fn generate_pd_url(pd_addr: &str, func: &str) -> String {
     format!("http://{}/pd/api/v1/{}", pd_addr, func)
 }
 
+// Most regions PD returns for one scan request
+const PD_REGION_SCAN_LIMIT: &str = "10000";
+
+// Estimate keys and bytes under `prefix` by summing the approximate
+// statistics PD keeps for every region overlapping the prefix. Regions at
+// the edges are counted whole, so this is an upper bound. Region bounds are
+// encoded keys, so the prefix range is encoded the same way and passed in
+// hex, which keeps binary keys intact. An empty end key means no bound.
+pub async fn do_async_count_approx(prefix: &str) -> Result<RedisValue, Error> {
+    let pd_addr = GLOBAL_PD_ADDRS.read().unwrap().first().cloned().unwrap_or_else(|| String::from("127.0.0.1:2379"));
+    let start_key = to_hex(&encode_memcomparable(&user_range_start(prefix.as_bytes())));
+    let end_key = prefix_end(prefix.as_bytes()).map(|k| to_hex(&encode_memcomparable(&k))).unwrap_or_default();
+    let url = generate_pd_url(&pd_addr, "regions/key");
+    let client = Client::new();
+    let resp: serde_json::Value = client.get(url)
+        .query(&[("key", start_key.as_str()), ("end_key", end_key.as_str()), ("format", "hex"), ("limit", PD_REGION_SCAN_LIMIT)])
+        .send().await?.json().await?;
+    let mut keys: u64 = 0;
+    let mut size_mb: u64 = 0;
+    let mut count: u64 = 0;
+    if let Some(regions) = resp["regions"].as_array() {
+        for region in regions {
+            keys += region["approximate_keys"].as_u64().unwrap_or(0);
+            size_mb += region["approximate_size"].as_u64().unwrap_or(0);
+            count += 1;
+        }
+    }
+    Ok(RedisValue::Array(vec![
+        "keys".into(),
+        (keys as i64).into(),
+        "bytes".into(),
+        ((size_mb * 1024 * 1024) as i64).into(),
+        "regions".into(),
+        (count as i64).into(),
+    ]))
+}
+
 pub fn pd_members(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
     let mut pd_addr: &str = "127.0.0.1:2379";
     if args.len() > 1 {
@@ -0,0 +1,53 @@
+use std::future::Future;
+use tikv_client::Error;
+use tokio::time::{sleep, Duration};
+use crate::config::get_config;
+
+#[derive(Clone, Copy)]
+pub enum RetryClass {
+    Read,
+    // Writes that store the same result when they run twice, like SET or
+    // DEL
+    Write,
+    // Writes computed from what they read, like INCR or LPUSH. An error
+    // from the commit leaves it unknown whether the write was applied, and
+    // running it again could apply it twice, so these are never retried.
+    NonIdempotent,
+}
+
+// Name of the error class `err` belongs to, as used in `retry-errors`
+fn error_class(err: &Error) -> Option<&'static str> {
+    match err {
+        Error::RegionError(_) | Error::RegionForKeyNotFound { .. } => Some("region"),
+        Error::Grpc(_) => Some("grpc"),
+        Error::KeyError(_) => Some("key"),
+        _ => None,
+    }
+}
+
+// Run `op` until it succeeds, fails with an error class that is not
+// configured as retryable, or runs out of attempts for its command class.
+// Backoff doubles from `retry-backoff-base` up to `retry-backoff-cap`.
+pub async fn with_retry<T, F, Fut>(class: RetryClass, mut op: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let config = get_config();
+    let max_attempts = match class {
+        RetryClass::Read => config.retry_read_attempts,
+        RetryClass::Write => config.retry_write_attempts,
+        RetryClass::NonIdempotent => 1,
+    };
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(err) if attempt < max_attempts && error_class(&err).is_some_and(|c| config.retry_errors.iter().any(|e| e.eq_ignore_ascii_case(c))) => {
+                let backoff = config.retry_backoff_base.saturating_mul(1 << (attempt - 1).min(16)).min(config.retry_backoff_cap);
+                sleep(Duration::from_millis(backoff)).await;
+                attempt += 1;
+            },
+            res => return res,
+        }
+    }
+}
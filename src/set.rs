@@ -0,0 +1,535 @@
+use std::collections::{BTreeSet, HashSet};
+use redis_module::{ RedisValue };
+use tikv_client::{ Error, Transaction };
+use crate::encoding::*;
+use crate::tikv::{ ReadBackend, get_snapshot, type_keys, expect_type, clear_key, paged_reply };
+use crate::config::get_config;
+use crate::txn::{ Reader, begin_txn, finish_txn };
+use crate::utils::{ glob_match, random_below, shuffle, to_hex };
+
+// A set is a meta entry of type Set, which also counts the members, plus
+// one entry with an empty value per member, see encode_set_key. The meta
+// entry exists as long as the set has members.
+
+const SET_PAGE_SIZE: u32 = 1000;
+
+// Members from the member key `start` on, scanned page by page. Only keys
+// are read, the values are empty. With a `max_bytes` other than 0 it stops
+// before the member that would take the members past it, and also returns
+// that member to continue from. The first member is always taken.
+async fn members_until(reader: &mut Reader<'_>, key: &[u8], mut start: Vec<u8>, max_bytes: u64) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), Error> {
+    let mut members = Vec::new();
+    let mut bytes: u64 = 0;
+    let (_, end) = set_range(key);
+    loop {
+        let keys = reader.scan_keys((start.clone()..end.clone()).into(), SET_PAGE_SIZE).await?;
+        let count = keys.len();
+        for member_key in keys.into_iter() {
+            start = member_key.into();
+            let member = decode_set_member(key, &start).to_vec();
+            bytes += member.len() as u64;
+            if max_bytes > 0 && bytes > max_bytes && !members.is_empty() {
+                return Ok((members, Some(member)));
+            }
+            members.push(member);
+            start.push(0);
+        }
+        if count < SET_PAGE_SIZE as usize {
+            return Ok((members, None));
+        }
+    }
+}
+
+// Every member of the set
+pub async fn read_members(reader: &mut Reader<'_>, key: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    Ok(members_until(reader, key, set_range(key).0, 0).await?.0)
+}
+
+async fn sadd(txn: &mut Transaction, key: &[u8], members: Vec<Vec<u8>>) -> Result<i64, Error> {
+    let member_keys: Vec<Vec<u8>> = members.iter().map(|member| encode_set_key(key, member)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(member_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let meta = expect_type(&found, key, DataType::Set)?;
+
+    let added: HashSet<Vec<u8>> = member_keys.into_iter().filter(|k| meta.is_none() || !found.contains_key(k)).collect();
+    if added.is_empty() {
+        return Ok(0);
+    }
+    for member_key in added.iter() {
+        txn.put(member_key.clone(), Vec::new()).await?;
+    }
+    let mut meta = meta.unwrap_or_else(|| KeyMeta::new(DataType::Set, 0));
+    meta.size += added.len() as u64;
+    txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    Ok(added.len() as i64)
+}
+
+// Add the members, replies how many of them are new
+pub async fn do_async_sadd(key: &str, members: Vec<String>) -> Result<RedisValue, Error> {
+    let members = members.into_iter().map(|m| m.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = sadd(&mut txn, key.as_bytes(), members).await;
+    let added = finish_txn(txn, res).await?;
+    Ok(added.into())
+}
+
+async fn srem(txn: &mut Transaction, key: &[u8], members: Vec<Vec<u8>>) -> Result<i64, Error> {
+    let member_keys: Vec<Vec<u8>> = members.iter().map(|member| encode_set_key(key, member)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(member_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let mut meta = match expect_type(&found, key, DataType::Set)? {
+        Some(meta) => meta,
+        None => return Ok(0),
+    };
+
+    let removed: HashSet<Vec<u8>> = member_keys.into_iter().filter(|k| found.contains_key(k)).collect();
+    if removed.is_empty() {
+        return Ok(0);
+    }
+    for member_key in removed.iter() {
+        txn.delete(member_key.clone()).await?;
+    }
+    meta.size = meta.size.saturating_sub(removed.len() as u64);
+    if meta.size == 0 {
+        txn.delete(encode_meta_key(key)).await?;
+    } else {
+        txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    }
+    Ok(removed.len() as i64)
+}
+
+// Remove the members, replies how many of them were in the set
+pub async fn do_async_srem(key: &str, members: Vec<String>) -> Result<RedisValue, Error> {
+    let members = members.into_iter().map(|m| m.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = srem(&mut txn, key.as_bytes(), members).await;
+    let removed = finish_txn(txn, res).await?;
+    Ok(removed.into())
+}
+
+// Every member. With max-reply-size set, or when continuing from
+// `cursor`, the members come in parts of about that many bytes, see
+// paged_reply.
+pub async fn do_async_smembers(key: &str, cursor: Option<Vec<u8>>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let max_bytes = get_config().max_reply_size;
+    let paged = max_bytes > 0 || cursor.is_some();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    if expect_type(&found, key, DataType::Set)?.is_none() {
+        let empty = RedisValue::Array(vec![]);
+        return Ok(if paged { paged_reply(empty, None) } else { empty });
+    }
+    let start = match cursor {
+        Some(member) => encode_set_key(key, &member),
+        None => set_range(key).0,
+    };
+    let (members, next) = members_until(&mut Reader::Snapshot(&mut snapshot), key, start, max_bytes).await?;
+    let members = RedisValue::Array(members.into_iter().map(RedisValue::from).collect());
+    Ok(if paged { paged_reply(members, next) } else { members })
+}
+
+// Cardinality from the meta entry, a single point read
+pub async fn do_async_scard(key: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let size = expect_type(&found, key, DataType::Set)?.map_or(0, |m| m.size);
+    Ok((size as i64).into())
+}
+
+// Membership of each member as 0 or 1 in argument order, point reads of
+// the member keys in one batch_get. A single member replies an integer
+// (SISMEMBER) unless `multi` asks for an array (SMISMEMBER).
+pub async fn do_async_sismember(key: &str, members: Vec<String>, multi: bool, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let member_keys: Vec<Vec<u8>> = members.iter().map(|member| encode_set_key(key, member.as_bytes())).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(member_keys.iter().cloned());
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    let exists = expect_type(&found, key, DataType::Set)?.is_some();
+    let mut flags = member_keys.iter().map(|k| RedisValue::Integer((exists && found.contains_key(k)) as i64));
+    if multi {
+        return Ok(RedisValue::Array(flags.collect()));
+    }
+    Ok(flags.next().unwrap_or(RedisValue::Integer(0)))
+}
+
+// Random point of the set's member keyspace to seek to
+fn random_seek(key: &[u8]) -> Vec<u8> {
+    encode_set_key(key, &random_below(u64::MAX).to_be_bytes())
+}
+
+// Up to `count` members at or after the key `from`, wrapping around to the
+// first member
+async fn members_from(reader: &mut Reader<'_>, key: &[u8], from: Vec<u8>, count: u64) -> Result<Vec<Vec<u8>>, Error> {
+    let count = count.min(u32::MAX as u64) as u32;
+    let (start, end) = set_range(key);
+    let mut keys = reader.scan_keys((from.clone()..end).into(), count).await?;
+    if keys.len() < count as usize {
+        keys.extend(reader.scan_keys((start..from).into(), count - keys.len() as u32).await?);
+    }
+    Ok(keys.into_iter().map(|k| decode_set_member(key, &Into::<Vec<u8>>::into(k)).to_vec()).collect())
+}
+
+// Up to `count` distinct random members of a set holding `size` members.
+// Asking for half the set or more reads it whole. Otherwise each member
+// comes from a seek to a random point of the member keyspace, so the cost
+// follows `count` and not the set size. The pick is not uniform: a member
+// that follows a wide gap in key order is found by more seek points.
+// Should the seeks keep landing on members already picked, the rest is
+// taken as one run of members from a last random point.
+async fn sample_members(reader: &mut Reader<'_>, key: &[u8], size: u64, count: u64) -> Result<Vec<Vec<u8>>, Error> {
+    if count.saturating_mul(2) >= size {
+        let mut members = read_members(reader, key).await?;
+        shuffle(&mut members);
+        members.truncate(count as usize);
+        return Ok(members);
+    }
+    let mut picked: HashSet<Vec<u8>> = HashSet::new();
+    for _ in 0..count * 2 {
+        if picked.len() as u64 == count {
+            break;
+        }
+        picked.extend(members_from(reader, key, random_seek(key), 1).await?);
+    }
+    if (picked.len() as u64) < count {
+        // `count` members in a row hold at least the missing ones
+        for member in members_from(reader, key, random_seek(key), count).await? {
+            if picked.len() as u64 == count {
+                break;
+            }
+            picked.insert(member);
+        }
+    }
+    let mut members: Vec<Vec<u8>> = picked.into_iter().collect();
+    shuffle(&mut members);
+    Ok(members)
+}
+
+// `count` random members that may repeat, one random seek each, or drawn
+// from the whole set when it is not larger than `count`
+async fn sample_with_repetition(reader: &mut Reader<'_>, key: &[u8], size: u64, count: u64) -> Result<Vec<Vec<u8>>, Error> {
+    let mut draws = Vec::new();
+    if size <= count {
+        let members = read_members(reader, key).await?;
+        if !members.is_empty() {
+            for _ in 0..count {
+                draws.push(members[random_below(members.len() as u64) as usize].clone());
+            }
+        }
+        return Ok(draws);
+    }
+    for _ in 0..count {
+        draws.extend(members_from(reader, key, random_seek(key), 1).await?);
+    }
+    Ok(draws)
+}
+
+// Reply of SPOP and SRANDMEMBER: one member (nil if none) without a count,
+// an array with one
+fn sample_reply(members: Vec<Vec<u8>>, count: Option<i64>) -> RedisValue {
+    let mut members = members.into_iter().map(RedisValue::from);
+    match count {
+        Some(_) => RedisValue::Array(members.collect()),
+        None => members.next().unwrap_or(RedisValue::Null),
+    }
+}
+
+// Random members the way Redis SRANDMEMBER picks them: a positive count
+// gives distinct members, a negative one may repeat them
+pub async fn do_async_srandmember(key: &str, count: Option<i64>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let size = expect_type(&found, key, DataType::Set)?.map_or(0, |m| m.size);
+    let want = count.unwrap_or(1);
+    let mut reader = Reader::Snapshot(&mut snapshot);
+    let members = if size == 0 || want == 0 {
+        Vec::new()
+    } else if want > 0 {
+        sample_members(&mut reader, key, size, want as u64).await?
+    } else {
+        sample_with_repetition(&mut reader, key, size, want.unsigned_abs()).await?
+    };
+    Ok(sample_reply(members, count))
+}
+
+async fn spop(txn: &mut Transaction, key: &[u8], count: u64) -> Result<Vec<Vec<u8>>, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = match expect_type(&found, key, DataType::Set)? {
+        Some(meta) => meta,
+        None => return Ok(Vec::new()),
+    };
+    let members = sample_members(&mut Reader::Txn(txn), key, meta.size, count).await?;
+    for member in members.iter() {
+        txn.delete(encode_set_key(key, member)).await?;
+    }
+    meta.size = meta.size.saturating_sub(members.len() as u64);
+    if meta.size == 0 {
+        txn.delete(encode_meta_key(key)).await?;
+    } else {
+        txn.put(encode_meta_key(key), encode_meta(&meta)).await?;
+    }
+    Ok(members)
+}
+
+// Remove and return random distinct members, picked like SRANDMEMBER and
+// deleted in the same transaction
+pub async fn do_async_spop(key: &str, count: Option<u64>) -> Result<RedisValue, Error> {
+    let members = if count == Some(0) {
+        Vec::new()
+    } else {
+        let mut txn = begin_txn().await?;
+        let res = spop(&mut txn, key.as_bytes(), count.unwrap_or(1)).await;
+        finish_txn(txn, res).await?
+    };
+    Ok(sample_reply(members, count.map(|c| c as i64)))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+// Meta entries of the sets under `keys`, None for missing ones, read with
+// one batch_get
+async fn set_metas(reader: &mut Reader<'_>, keys: &[Vec<u8>]) -> Result<Vec<Option<KeyMeta>>, Error> {
+    let mut wanted = Vec::new();
+    for key in keys.iter() {
+        wanted.extend(type_keys(key));
+    }
+    let found = reader.batch_get(wanted).await?;
+    keys.iter().map(|key| expect_type(&found, key, DataType::Set)).collect()
+}
+
+// One page of members of the set, from the member key `start` on, and the
+// key to continue from when there may be more
+async fn member_page(reader: &mut Reader<'_>, key: &[u8], start: Vec<u8>) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), Error> {
+    let (_, end) = set_range(key);
+    let keys = reader.scan_keys((start..end).into(), SET_PAGE_SIZE).await?;
+    let full = keys.len() == SET_PAGE_SIZE as usize;
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(Into::into).collect();
+    let next = match keys.last() {
+        Some(last) if full => {
+            let mut next = last.clone();
+            next.push(0);
+            Some(next)
+        },
+        _ => None,
+    };
+    Ok((keys.iter().map(|k| decode_set_member(key, k).to_vec()).collect(), next))
+}
+
+// The members among `members` that belong to the set, one batch_get
+async fn present_members(reader: &mut Reader<'_>, key: &[u8], members: &[Vec<u8>]) -> Result<HashSet<Vec<u8>>, Error> {
+    let found = reader.batch_get(members.iter().map(|m| encode_set_key(key, m)).collect()).await?;
+    Ok(members.iter().filter(|m| found.contains_key(&encode_set_key(key, m))).cloned().collect())
+}
+
+// Walk the intersection (Inter) or the difference (Diff) of the sets page
+// by page: Inter walks the smallest set and Diff the first one, keeping the
+// members of each page the other sets agree with, checked with one
+// batch_get per set and page. `visit` gets each filtered page and returns
+// false to stop early.
+async fn walk_filtered<F>(reader: &mut Reader<'_>, op: SetOp, keys: &[Vec<u8>], metas: &[Option<KeyMeta>], mut visit: F) -> Result<(), Error>
+where
+    F: FnMut(Vec<Vec<u8>>) -> bool,
+{
+    // A missing set makes the intersection empty
+    if op == SetOp::Inter && metas.iter().any(|m| m.is_none()) {
+        return Ok(());
+    }
+    let walked = if op == SetOp::Inter {
+        (0..keys.len()).min_by_key(|i| metas[*i].map_or(0, |m| m.size)).unwrap_or(0)
+    } else {
+        0
+    };
+    if keys.is_empty() || metas[walked].is_none() {
+        return Ok(());
+    }
+    let others: Vec<&Vec<u8>> = keys.iter().enumerate()
+        .filter(|(i, _)| *i != walked && metas[*i].is_some())
+        .map(|(_, key)| key)
+        .collect();
+    let key = &keys[walked];
+    let mut start = set_range(key).0;
+    loop {
+        let (mut members, next) = member_page(reader, key, start).await?;
+        for other in others.iter() {
+            if members.is_empty() {
+                break;
+            }
+            let present = present_members(reader, other, &members).await?;
+            members.retain(|m| present.contains(m) == (op == SetOp::Inter));
+        }
+        if !visit(members) {
+            return Ok(());
+        }
+        match next {
+            Some(next) => start = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+// Members of the intersection, union or difference of the sets. Union has
+// to collect every member, see walk_filtered for the others.
+async fn set_algebra(reader: &mut Reader<'_>, op: SetOp, keys: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, Error> {
+    let metas = set_metas(reader, keys).await?;
+    let mut result = Vec::new();
+    if op == SetOp::Union {
+        let mut union = BTreeSet::new();
+        for (key, meta) in keys.iter().zip(metas.iter()) {
+            if meta.is_some() {
+                union.extend(read_members(reader, key).await?);
+            }
+        }
+        result.extend(union);
+        return Ok(result);
+    }
+    walk_filtered(reader, op, keys, &metas, |members| {
+        result.extend(members);
+        true
+    }).await?;
+    Ok(result)
+}
+
+pub async fn do_async_set_algebra(op: SetOp, keys: Vec<String>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.into_bytes()).collect();
+    let mut snapshot = get_snapshot(backend).await?;
+    let members = set_algebra(&mut Reader::Snapshot(&mut snapshot), op, &keys).await?;
+    Ok(RedisValue::Array(members.into_iter().map(RedisValue::from).collect()))
+}
+
+async fn set_algebra_store(txn: &mut Transaction, op: SetOp, destination: &[u8], keys: &[Vec<u8>]) -> Result<i64, Error> {
+    let members = set_algebra(&mut Reader::Txn(txn), op, keys).await?;
+    // The destination may be one of the sources, it is only cleared once
+    // the result is known
+    clear_key(txn, destination).await?;
+    if members.is_empty() {
+        return Ok(0);
+    }
+    for member in members.iter() {
+        txn.put(encode_set_key(destination, member), Vec::new()).await?;
+    }
+    let mut meta = KeyMeta::new(DataType::Set, 0);
+    meta.size = members.len() as u64;
+    txn.put(encode_meta_key(destination), encode_meta(&meta)).await?;
+    Ok(members.len() as i64)
+}
+
+// Store the result in `destination`, replacing whatever it held, in the
+// same transaction as the reads. Replies the result's cardinality.
+pub async fn do_async_set_algebra_store(op: SetOp, destination: &str, keys: Vec<String>) -> Result<RedisValue, Error> {
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = set_algebra_store(&mut txn, op, destination.as_bytes(), &keys).await;
+    let size = finish_txn(txn, res).await?;
+    Ok(size.into())
+}
+
+// Cardinality of the intersection, counted page by page without keeping
+// the members, stopping once it reaches `limit` (0 for no limit)
+pub async fn do_async_sintercard(keys: Vec<String>, limit: u64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.into_bytes()).collect();
+    let mut snapshot = get_snapshot(backend).await?;
+    let mut reader = Reader::Snapshot(&mut snapshot);
+    let metas = set_metas(&mut reader, &keys).await?;
+    let mut count: u64 = 0;
+    walk_filtered(&mut reader, SetOp::Inter, &keys, &metas, |members| {
+        count += members.len() as u64;
+        limit == 0 || count < limit
+    }).await?;
+    if limit > 0 {
+        count = count.min(limit);
+    }
+    Ok((count as i64).into())
+}
+
+async fn smove(txn: &mut Transaction, source: &[u8], destination: &[u8], member: &[u8]) -> Result<bool, Error> {
+    let source_key = encode_set_key(source, member);
+    let destination_key = encode_set_key(destination, member);
+    let mut wanted = type_keys(source);
+    wanted.extend(type_keys(destination));
+    wanted.push(source_key.clone());
+    wanted.push(destination_key.clone());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let source_meta = expect_type(&found, source, DataType::Set)?;
+    let destination_meta = expect_type(&found, destination, DataType::Set)?;
+    let mut source_meta = match source_meta {
+        Some(meta) if found.contains_key(&source_key) => meta,
+        _ => return Ok(false),
+    };
+    if source == destination {
+        return Ok(true);
+    }
+
+    txn.delete(source_key).await?;
+    source_meta.size -= 1;
+    if source_meta.size == 0 {
+        txn.delete(encode_meta_key(source)).await?;
+    } else {
+        txn.put(encode_meta_key(source), encode_meta(&source_meta)).await?;
+    }
+    if destination_meta.is_none() || !found.contains_key(&destination_key) {
+        txn.put(destination_key, Vec::new()).await?;
+        let mut meta = destination_meta.unwrap_or_else(|| KeyMeta::new(DataType::Set, 0));
+        meta.size += 1;
+        txn.put(encode_meta_key(destination), encode_meta(&meta)).await?;
+    }
+    Ok(true)
+}
+
+// Move a member from one set to another in one transaction, so readers
+// see it in exactly one of them. Replies 1 if moved, 0 if `source` did not
+// hold it.
+pub async fn do_async_smove(source: &str, destination: &str, member: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = smove(&mut txn, source.as_bytes(), destination.as_bytes(), member.as_bytes()).await;
+    let moved = finish_txn(txn, res).await?;
+    Ok((moved as i64).into())
+}
+
+// One SSCAN step: up to `count` member keys from `cursor` on (None starts
+// at the first member), keeping the members matching `pattern`. The reply
+// cursor is the member key suffix to continue from in hex, or "0" once the
+// set is exhausted, so members present for the whole iteration are
+// returned exactly once.
+pub async fn do_async_sscan(key: &str, cursor: Option<Vec<u8>>, pattern: Option<Vec<u8>>, count: u32, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    if expect_type(&found, key, DataType::Set)?.is_none() {
+        return Ok(RedisValue::Array(vec!["0".into(), RedisValue::Array(vec![])]));
+    }
+    let (first, end) = set_range(key);
+    let start = match cursor {
+        Some(member) => encode_set_key(key, &member),
+        None => first,
+    };
+    let keys = Reader::Snapshot(&mut snapshot).scan_keys((start..end).into(), count).await?;
+    let exhausted = keys.len() < count as usize;
+    let members: Vec<Vec<u8>> = keys.into_iter().map(|k| decode_set_member(key, &Into::<Vec<u8>>::into(k)).to_vec()).collect();
+    let next: RedisValue = match members.last() {
+        Some(last) if !exhausted => {
+            let mut next = last.clone();
+            next.push(0);
+            to_hex(&next).into()
+        },
+        _ => "0".into(),
+    };
+    let members = members.into_iter()
+        .filter(|m| match &pattern {
+            Some(pattern) => glob_match(pattern, m),
+            None => true,
+        })
+        .map(RedisValue::from)
+        .collect();
+    Ok(RedisValue::Array(vec![next, RedisValue::Array(members)]))
+}
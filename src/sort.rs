@@ -0,0 +1,195 @@
+use std::cmp::Ordering;
+use redis_module::{ RedisValue };
+use tikv_client::{ Error, Snapshot };
+use crate::encoding::*;
+use crate::tikv::{ ReadBackend, get_snapshot, live_type, read_strings, type_keys, wrong_type };
+use crate::txn::Reader;
+use crate::hash::read_hash_values;
+use crate::list::read_list;
+use crate::set::read_members;
+use crate::numeric::parse_float;
+use crate::utils::now_millis;
+
+// tikv.sort reads the elements of a list or set from one snapshot, sorts
+// them in the module and replies with the elements, or with values looked
+// up through GET patterns. A pattern names a string key, or a hash field
+// after "->", with the first `*` replaced by the element. The lookups of
+// every element for one pattern are a single batch_get per kind, see
+// lookup, and GET lookups are only done for the elements within LIMIT.
+
+// Options of SORT: the BY pattern (one without `*` leaves the elements
+// unsorted), the LIMIT offset and count, the GET patterns in reply order
+#[derive(Clone)]
+pub struct SortOptions {
+    pub by: Option<String>,
+    pub limit: Option<(i64, i64)>,
+    pub gets: Vec<String>,
+    pub desc: bool,
+    pub alpha: bool,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// Key and optional hash field that `pattern` names for `element`, None
+// when the pattern has no `*`
+fn resolve(pattern: &[u8], element: &[u8]) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    let star = pattern.iter().position(|b| *b == b'*')?;
+    let (key_part, field) = match find(&pattern[star + 1..], b"->") {
+        Some(at) if star + 1 + at + 2 < pattern.len() => {
+            let split = star + 1 + at;
+            (&pattern[..split], Some(pattern[split + 2..].to_vec()))
+        },
+        _ => (pattern, None),
+    };
+    let mut key = key_part[..star].to_vec();
+    key.extend_from_slice(element);
+    key.extend_from_slice(&key_part[star + 1..]);
+    Some((key, field))
+}
+
+// Value that `pattern` names for each element, `#` being the element
+// itself. String keys are read with one batch_get, hash fields with
+// another.
+async fn lookup(snapshot: &mut Snapshot, pattern: &str, elements: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    if pattern == "#" {
+        return Ok(elements.iter().cloned().map(Some).collect());
+    }
+    let mut values = vec![None; elements.len()];
+    let mut keys = Vec::new();
+    let mut fields = Vec::new();
+    for (i, element) in elements.iter().enumerate() {
+        match resolve(pattern.as_bytes(), element) {
+            Some((key, None)) => keys.push((i, key)),
+            Some((key, Some(field))) => fields.push((i, (key, field))),
+            None => {},
+        }
+    }
+    if !keys.is_empty() {
+        let wanted: Vec<Vec<u8>> = keys.iter().map(|(_, key)| key.clone()).collect();
+        let found = read_strings(Reader::Snapshot(&mut *snapshot), &wanted).await?;
+        for ((i, _), value) in keys.iter().zip(found.into_iter()) {
+            values[*i] = value;
+        }
+    }
+    if !fields.is_empty() {
+        let wanted: Vec<(Vec<u8>, Vec<u8>)> = fields.iter().map(|(_, pair)| pair.clone()).collect();
+        let found = read_hash_values(&mut Reader::Snapshot(&mut *snapshot), &wanted).await?;
+        for ((i, _), value) in fields.iter().zip(found.into_iter()) {
+            values[*i] = value;
+        }
+    }
+    Ok(values)
+}
+
+enum Weight {
+    Number(f64),
+    Bytes(Vec<u8>),
+}
+
+fn compare(a: &(Weight, Vec<u8>), b: &(Weight, Vec<u8>)) -> Ordering {
+    let by_weight = match (&a.0, &b.0) {
+        (Weight::Number(x), Weight::Number(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (Weight::Bytes(x), Weight::Bytes(y)) => x.cmp(y),
+        _ => Ordering::Equal,
+    };
+    by_weight.then_with(|| a.1.cmp(&b.1))
+}
+
+// Offset and count of LIMIT turned into a window of `len` elements
+fn limit_window(limit: Option<(i64, i64)>, len: usize) -> (usize, usize) {
+    match limit {
+        None => (0, len),
+        Some((offset, count)) => {
+            let start = (offset.max(0) as usize).min(len);
+            let take = if count < 0 { len - start } else { (count as usize).min(len - start) };
+            (start, take)
+        },
+    }
+}
+
+async fn sort(snapshot: &mut Snapshot, key: &[u8], opts: &SortOptions) -> Result<RedisValue, Error> {
+    let mut found = Reader::Snapshot(&mut *snapshot).batch_get(type_keys(key)).await?;
+    let meta = found.remove(&encode_meta_key(key));
+    let mut elements = match live_type(found.contains_key(key), meta.as_ref(), now_millis()) {
+        None => Vec::new(),
+        Some(DataType::List) => {
+            let meta = meta.as_deref().and_then(decode_meta).unwrap_or_else(|| KeyMeta::new(DataType::List, 0));
+            read_list(Reader::Snapshot(&mut *snapshot), key, &meta).await?
+        },
+        Some(DataType::Set) => read_members(&mut Reader::Snapshot(&mut *snapshot), key).await?,
+        Some(_) => return Err(wrong_type()),
+    };
+
+    let nosort = matches!(&opts.by, Some(by) if !by.contains('*'));
+    if !nosort {
+        let weights = match &opts.by {
+            Some(by) => lookup(snapshot, by, &elements).await?,
+            None => elements.iter().cloned().map(Some).collect(),
+        };
+        let mut weighted = Vec::with_capacity(elements.len());
+        for (element, weight) in elements.into_iter().zip(weights.into_iter()) {
+            let weight = match weight {
+                Some(raw) if !opts.alpha => Weight::Number(parse_float(&raw).ok_or_else(|| {
+                    tikv_client::Error::StringError(String::from("ERR One or more scores can't be converted into double"))
+                })?),
+                None if !opts.alpha => Weight::Number(0.0),
+                raw => Weight::Bytes(raw.unwrap_or_default()),
+            };
+            weighted.push((weight, element));
+        }
+        weighted.sort_by(compare);
+        if opts.desc {
+            weighted.reverse();
+        }
+        elements = weighted.into_iter().map(|(_, element)| element).collect();
+    }
+
+    let (start, take) = limit_window(opts.limit, elements.len());
+    let elements: Vec<Vec<u8>> = elements.into_iter().skip(start).take(take).collect();
+    if opts.gets.is_empty() {
+        return Ok(RedisValue::Array(elements.into_iter().map(RedisValue::from).collect()));
+    }
+    let mut columns = Vec::with_capacity(opts.gets.len());
+    for pattern in opts.gets.iter() {
+        columns.push(lookup(snapshot, pattern, &elements).await?);
+    }
+    let mut values = Vec::with_capacity(elements.len() * columns.len());
+    for i in 0..elements.len() {
+        for column in columns.iter() {
+            values.push(column[i].clone().into());
+        }
+    }
+    Ok(RedisValue::Array(values))
+}
+
+// Elements of a list or set sorted numerically, or byte-wise with ALPHA,
+// see SortOptions. Missing keys sort as an empty list.
+pub async fn do_async_sort(key: &str, opts: SortOptions, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let mut snapshot = get_snapshot(backend).await?;
+    sort(&mut snapshot, key.as_bytes(), &opts).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_patterns() {
+        assert_eq!(resolve(b"weight_*", b"3"), Some((b"weight_3".to_vec(), None)));
+        assert_eq!(resolve(b"obj_*->w", b"3"), Some((b"obj_3".to_vec(), Some(b"w".to_vec()))));
+        assert_eq!(resolve(b"*_x->", b"3"), Some((b"3_x->".to_vec(), None)));
+        assert_eq!(resolve(b"nosort", b"3"), None);
+    }
+
+    #[test]
+    fn limit_window_clamps() {
+        assert_eq!(limit_window(None, 5), (0, 5));
+        assert_eq!(limit_window(Some((1, 2)), 5), (1, 2));
+        assert_eq!(limit_window(Some((-3, 2)), 5), (0, 2));
+        assert_eq!(limit_window(Some((4, 10)), 5), (4, 1));
+        assert_eq!(limit_window(Some((9, 1)), 5), (5, 0));
+        assert_eq!(limit_window(Some((1, -1)), 5), (1, 4));
+    }
+}
@@ -1,9 +1,85 @@
 use redis_module::{ RedisValue };
-use tikv_client::{RawClient, Error, Key, KvPair};
-use crate::init::GLOBAL_CLIENT;
-use std::collections::HashMap;
+use tikv_client::{RawClient, TransactionClient, TransactionOptions, Snapshot, Transaction, Timestamp, TimestampExt, BoundRange, Error, KvPair};
+use crate::init::{ GLOBAL_CLIENT, GLOBAL_PD_ADDRS, GLOBAL_TXN_CLIENT, GLOBAL_READ_BACKEND, GLOBAL_READ_SNAPSHOT, GLOBAL_SCAN_SNAPSHOTS, GLOBAL_WAITING_POPS, GLOBAL_INFLIGHT, GLOBAL_SWITCHING };
+use crate::config::get_config;
+use crate::encoding::*;
+use crate::txn::{ Reader, begin_txn, delete_range, finish_txn };
+use crate::hash::{ delete_hash_fields, fields_reply, read_fields };
+use crate::list::read_list;
+use crate::set::read_members;
+use crate::zset::{ entries_reply, read_scores };
+use crate::blocking::{ park, still_waiting, stop_waiting, wait_deadline, wake_client, wake_key };
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use crate::utils::{ abort_client_tasks, glob_match, now_millis, to_hex };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use tokio::time::{sleep, Duration, Instant};
+
+// Which snapshot serves reads: one taken at the current timestamp, or one
+// pinned to a fixed timestamp version by tikv.readsnapshot. There is no raw
+// mode, nothing writes the raw keyspace anymore, see tikv.migrateraw.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReadBackend {
+    Txn,
+    Pinned(u64),
+}
+
+pub const DEFAULT_READ_BACKEND: ReadBackend = ReadBackend::Txn;
+
+impl ReadBackend {
+    pub fn parse(name: &str) -> Option<ReadBackend> {
+        match name.to_uppercase().as_str() {
+            "TXN" => Some(ReadBackend::Txn),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReadBackend::Txn => "txn",
+            ReadBackend::Pinned(_) => "snapshot",
+        }
+    }
+}
+
+pub fn get_read_backend(cid: u64) -> ReadBackend {
+    if let Some(version) = GLOBAL_READ_SNAPSHOT.read().unwrap().get(&cid) {
+        return ReadBackend::Pinned(*version);
+    }
+    match GLOBAL_READ_BACKEND.read().unwrap().get(&cid) {
+        Some(backend) => *backend,
+        None => DEFAULT_READ_BACKEND,
+    }
+}
+
+pub fn set_read_backend(cid: u64, backend: ReadBackend) {
+    let mut backends = GLOBAL_READ_BACKEND.write().unwrap();
+    if backend == DEFAULT_READ_BACKEND {
+        backends.remove(&cid);
+    } else {
+        backends.insert(cid, backend);
+    }
+}
+
+// Drop the per-connection read settings of a client that disconnected,
+// including a pinned snapshot, stop its blocking command and abort its
+// reads still in flight, so nothing outlives the connection
+pub fn forget_client(cid: u64) {
+    abort_client_tasks(cid);
+    GLOBAL_READ_BACKEND.write().unwrap().remove(&cid);
+    GLOBAL_WAITING_POPS.write().unwrap().remove(&cid);
+    wake_client(cid);
+    unpin_snapshot(cid);
+}
+
+fn check_switching() -> Result<(), Error> {
+    if GLOBAL_SWITCHING.load(Ordering::SeqCst) {
+        return Err(tikv_client::Error::StringError(String::from("Cluster switch in progress")));
+    }
+    Ok(())
+}
 
 pub fn get_client() -> Result<Box<RawClient>, Error> {
+    check_switching()?;
     let guard = GLOBAL_CLIENT.read().unwrap();
     match guard.as_ref() {
         Some(val) => {
@@ -14,90 +90,1341 @@ pub fn get_client() -> Result<Box<RawClient>, Error> {
     }
 }
 
+pub fn get_txn_client() -> Result<Box<TransactionClient>, Error> {
+    check_switching()?;
+    let guard = GLOBAL_TXN_CLIENT.read().unwrap();
+    match guard.as_ref() {
+        Some(val) => {
+            let client = val.clone();
+            Ok(client)
+        },
+        None => Err(tikv_client::Error::StringError(String::from("Not connected")))
+    }
+}
+
+pub async fn get_snapshot(backend: ReadBackend) -> Result<Snapshot, Error> {
+    let client = get_txn_client()?;
+    let ts = match backend {
+        ReadBackend::Pinned(version) => Timestamp::from_version(version),
+        _ => client.current_timestamp().await?,
+    };
+    Ok(client.snapshot(ts, TransactionOptions::new_optimistic()))
+}
+
+pub async fn do_async_pin_snapshot(cid: u64) -> Result<RedisValue, Error> {
+    let client = get_txn_client()?;
+    let version = client.current_timestamp().await?.version();
+    GLOBAL_READ_SNAPSHOT.write().unwrap().insert(cid, version);
+    Ok((version as i64).into())
+}
+
+pub fn unpin_snapshot(cid: u64) {
+    GLOBAL_READ_SNAPSHOT.write().unwrap().remove(&cid);
+}
+
+// Milliseconds a snapshot pinned by tikv.scankeys SNAPSHOT stays usable
+// after its last use. TiKV's default GC life time is 10 minutes too:
+// reading at an older version fails once GC has moved past it.
+const SCAN_SNAPSHOT_TTL: u64 = 600_000;
+
+static SCAN_SNAPSHOT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Pin the current timestamp for a scan iteration. Snapshots are kept by id
+// in GLOBAL_SCAN_SNAPSHOTS, not per connection, so a cursor naming one
+// keeps working from another connection.
+async fn pin_scan_snapshot() -> Result<u64, Error> {
+    let version = get_txn_client()?.current_timestamp().await?.version();
+    let id = SCAN_SNAPSHOT_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    let mut snapshots = GLOBAL_SCAN_SNAPSHOTS.write().unwrap();
+    let now = now_millis();
+    snapshots.retain(|_, (_, expire_at)| *expire_at > now);
+    snapshots.insert(id, (version, now + SCAN_SNAPSHOT_TTL));
+    Ok(id)
+}
+
+// Version of a pinned scan snapshot, pushing its expiration back
+fn scan_snapshot_version(id: u64) -> Result<u64, Error> {
+    let mut snapshots = GLOBAL_SCAN_SNAPSHOTS.write().unwrap();
+    let now = now_millis();
+    match snapshots.get_mut(&id) {
+        Some((version, expire_at)) if *expire_at > now => {
+            *expire_at = now + SCAN_SNAPSHOT_TTL;
+            Ok(*version)
+        },
+        _ => {
+            snapshots.remove(&id);
+            Err(tikv_client::Error::StringError(String::from("ERR scan snapshot expired or unknown, start over from cursor 0")))
+        },
+    }
+}
+
+fn release_scan_snapshot(id: u64) {
+    GLOBAL_SCAN_SNAPSHOTS.write().unwrap().remove(&id);
+}
+
 pub async fn do_async_connect(addrs: Vec<String>) -> Result<RedisValue, Error> {
-    let client = RawClient::new(addrs).await?;
+    let mut client = RawClient::new(addrs.clone()).await?;
+    if get_config().atomic_cas {
+        client = client.with_atomic_for_cas();
+    }
+    let txn_client = TransactionClient::new(addrs.clone()).await?;
     GLOBAL_CLIENT.write().unwrap().replace(Box::new(client));
+    GLOBAL_TXN_CLIENT.write().unwrap().replace(Box::new(txn_client));
+    *GLOBAL_PD_ADDRS.write().unwrap() = addrs;
     Ok("OK".into())
 }
 
-pub async fn do_async_get(key: &str) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let value = client.get(key.to_owned()).await?;
+// Stop handing out clients, wait up to `timeout_ms` for in-flight tasks to
+// finish, then connect to the new PD addresses. If connecting fails the old
+// clients are kept and traffic resumes on the old cluster.
+pub async fn do_async_switch_cluster(addrs: Vec<String>, timeout_ms: u64) -> Result<RedisValue, Error> {
+    if GLOBAL_SWITCHING.swap(true, Ordering::SeqCst) {
+        return Err(tikv_client::Error::StringError(String::from("Cluster switch already in progress")));
+    }
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    // This task is counted as in-flight itself
+    while GLOBAL_INFLIGHT.load(Ordering::SeqCst) > 1 && Instant::now() < deadline {
+        sleep(Duration::from_millis(10)).await;
+    }
+    let pending = GLOBAL_INFLIGHT.load(Ordering::SeqCst) - 1;
+    let res = do_async_connect(addrs).await;
+    if res.is_ok() {
+        // Pinned timestamps belong to the old cluster's PD
+        GLOBAL_READ_SNAPSHOT.write().unwrap().clear();
+    }
+    GLOBAL_SWITCHING.store(false, Ordering::SeqCst);
+    res?;
+    Ok(RedisValue::Array(vec!["OK".into(), "pending".into(), pending.into()]))
+}
+
+// Drop the current clients and connect again to the same PD addresses
+pub async fn do_async_pool_flush() -> Result<RedisValue, Error> {
+    let addrs = GLOBAL_PD_ADDRS.read().unwrap().clone();
+    if addrs.is_empty() {
+        return Err(tikv_client::Error::StringError(String::from("Not connected")));
+    }
+    do_async_connect(addrs).await
+}
+
+pub fn pool_status() -> RedisValue {
+    let addrs = GLOBAL_PD_ADDRS.read().unwrap().clone();
+    let state = |connected: bool| if connected { "connected" } else { "closed" };
+    RedisValue::Array(vec![
+        "pd".into(),
+        addrs.into(),
+        "raw_client".into(),
+        state(GLOBAL_CLIENT.read().unwrap().is_some()).into(),
+        "txn_client".into(),
+        state(GLOBAL_TXN_CLIENT.read().unwrap().is_some()).into(),
+    ])
+}
+
+pub fn wrong_type() -> Error {
+    tikv_client::Error::StringError(String::from("WRONGTYPE Operation against a key holding the wrong kind of value"))
+}
+
+// A string key is its value stored under the key itself, plus a meta entry
+// when it carries an expiration. Expired keys read as missing.
+pub fn live_string(data: Option<Vec<u8>>, meta: Option<Vec<u8>>, now: u64) -> Result<(Option<Vec<u8>>, Option<KeyMeta>), Error> {
+    match meta.as_deref().and_then(decode_meta) {
+        Some(meta) if meta.is_expired(now) => Ok((None, None)),
+        Some(meta) if meta.data_type != DataType::String => Err(wrong_type()),
+        meta => Ok((data, meta)),
+    }
+}
+
+async fn read_string(mut reader: Reader<'_>, key: &[u8]) -> Result<(Option<Vec<u8>>, Option<KeyMeta>), Error> {
+    let meta_key = encode_meta_key(key);
+    let mut found = reader.batch_get(vec![key.to_vec(), meta_key.clone()]).await?;
+    live_string(found.remove(key), found.remove(&meta_key), now_millis())
+}
+
+// Values of several string keys, None for missing, expired or non-string keys
+pub async fn read_strings(mut reader: Reader<'_>, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let mut wanted = keys.to_vec();
+    wanted.extend(keys.iter().map(|k| encode_meta_key(k)));
+    let found = reader.batch_get(wanted).await?;
+    let now = now_millis();
+    Ok(keys.iter().map(|k| {
+        let meta = found.get(&encode_meta_key(k)).cloned();
+        live_string(found.get(k).cloned(), meta, now).ok().and_then(|(value, _)| value)
+    }).collect())
+}
+
+// Type of a live key given whether its data entry exists (only strings
+// keep data under the key itself) and its raw meta entry
+pub fn live_type(has_data: bool, meta: Option<&Vec<u8>>, now: u64) -> Option<DataType> {
+    match meta.and_then(|raw| decode_meta(raw)) {
+        Some(meta) if meta.is_expired(now) => None,
+        Some(meta) => Some(meta.data_type),
+        None if has_data => Some(DataType::String),
+        None => None,
+    }
+}
+
+// Entries to read to find out a key's type, see expect_type
+pub fn type_keys(key: &[u8]) -> Vec<Vec<u8>> {
+    vec![key.to_vec(), encode_meta_key(key)]
+}
+
+// Meta entry of `key` when it holds a live value of type `expected`, None
+// when it does not exist. `found` must hold the result of reading
+// type_keys(key). Any other type is a WRONGTYPE error.
+pub fn expect_type(found: &HashMap<Vec<u8>, Vec<u8>>, key: &[u8], expected: DataType) -> Result<Option<KeyMeta>, Error> {
+    let meta = found.get(&encode_meta_key(key)).and_then(|raw| decode_meta(raw));
+    match meta {
+        Some(meta) if meta.is_expired(now_millis()) => Ok(None),
+        Some(meta) if meta.data_type == expected => Ok(Some(meta)),
+        Some(_) => Err(wrong_type()),
+        None if found.contains_key(key) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+// Whether each key holds a live value of any type
+async fn keys_exist(mut reader: Reader<'_>, keys: &[Vec<u8>]) -> Result<Vec<bool>, Error> {
+    let mut wanted = keys.to_vec();
+    wanted.extend(keys.iter().map(|k| encode_meta_key(k)));
+    let found = reader.batch_get(wanted).await?;
+    let now = now_millis();
+    Ok(keys.iter().map(|k| live_type(found.contains_key(k), found.get(&encode_meta_key(k)), now).is_some()).collect())
+}
+
+// Type a key is stored as whether or not it expired, for cleaning up: an
+// expired collection still has its entries until they are deleted
+fn stored_type(has_data: bool, meta: Option<&Vec<u8>>) -> Option<DataType> {
+    match meta.and_then(|raw| decode_meta(raw)) {
+        Some(meta) => Some(meta.data_type),
+        None if has_data => Some(DataType::String),
+        None => None,
+    }
+}
+
+// Delete what a key of type `data_type` stores, except its meta entry
+async fn delete_data(txn: &mut Transaction, key: &[u8], data_type: DataType) -> Result<(), Error> {
+    match data_type {
+        DataType::String => txn.delete(key.to_vec()).await,
+        DataType::Hash => delete_hash_fields(txn, key).await,
+        DataType::List => delete_range(txn, list_range(key)).await,
+        DataType::Set => delete_range(txn, set_range(key)).await,
+        DataType::ZSet => {
+            delete_range(txn, zset_member_range(key)).await?;
+            delete_range(txn, zset_score_range(key)).await
+        },
+    }
+}
+
+// Clear keys about to be overwritten by a string if they hold another type
+async fn drop_other_types(txn: &mut Transaction, keys: &[Vec<u8>]) -> Result<(), Error> {
+    let found = Reader::Txn(txn).batch_get(keys.iter().map(|k| encode_meta_key(k)).collect()).await?;
+    for key in keys.iter() {
+        match stored_type(false, found.get(&encode_meta_key(key))) {
+            Some(DataType::String) | None => {},
+            Some(data_type) => delete_data(txn, key, data_type).await?,
+        }
+    }
+    Ok(())
+}
+
+// Store a string value, `expire_at` being the expiration in Unix ms or 0
+async fn write_string(txn: &mut Transaction, key: &[u8], value: Vec<u8>, expire_at: u64) -> Result<(), Error> {
+    txn.put(key.to_vec(), value).await?;
+    let meta_key = encode_meta_key(key);
+    if expire_at > 0 {
+        txn.put(meta_key, encode_meta(&KeyMeta::new(DataType::String, expire_at))).await
+    } else {
+        txn.delete(meta_key).await
+    }
+}
+
+// Delete the entry under the key itself and its meta entry
+async fn delete_string(txn: &mut Transaction, key: &[u8]) -> Result<(), Error> {
+    txn.delete(key.to_vec()).await?;
+    txn.delete(encode_meta_key(key)).await
+}
+
+// Remove whatever `key` holds, of any type, before a command stores a new
+// value there
+pub async fn clear_key(txn: &mut Transaction, key: &[u8]) -> Result<(), Error> {
+    drop_other_types(txn, &[key.to_vec()]).await?;
+    delete_string(txn, key).await
+}
+
+// Value of a string key through the given read backend
+async fn read_value(key: &[u8], backend: ReadBackend) -> Result<Option<Vec<u8>>, Error> {
+    let mut snapshot = get_snapshot(backend).await?;
+    Ok(read_string(Reader::Snapshot(&mut snapshot), key).await?.0)
+}
+
+async fn read_values(keys: &[Vec<u8>], backend: ReadBackend) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let mut snapshot = get_snapshot(backend).await?;
+    read_strings(Reader::Snapshot(&mut snapshot), keys).await
+}
+
+pub async fn do_async_get(key: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let value = read_value(key.as_bytes(), backend).await?;
     Ok(value.into())
 }
 
+// Values are stored whole, so the length comes from reading the value.
+// It is not kept in KeyMeta: strings without an expiration have no meta
+// entry, and giving every string one would add a write to each SET and put
+// every string key in the scan of the background expiry purge.
+pub async fn do_async_strlen(key: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let value = read_value(key.as_bytes(), backend).await?;
+    Ok(value.map_or(0, |v| v.len()).into())
+}
+
+// Bytes start..=end of the value, negative offsets counting from the end.
+// Values are stored whole, so the full value is read and then sliced.
+pub async fn do_async_getrange(key: &str, start: i64, end: i64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let value = read_value(key.as_bytes(), backend).await?.unwrap_or_default();
+    let len = value.len() as i64;
+    let start = if start < 0 { (start + len).max(0) } else { start };
+    let end = if end < 0 { (end + len).max(0) } else { end.min(len - 1) };
+    if len == 0 || start > end {
+        return Ok(Vec::<u8>::new().into());
+    }
+    Ok(value[start as usize..=end as usize].to_vec().into())
+}
+
 pub async fn do_async_get_raw(key: &str) -> Result<Vec<u8>, Error> {
-    let client = get_client()?;
-    let value = client.get(key.to_owned()).await?;
-    Ok(value.unwrap())
+    let value = read_value(key.as_bytes(), DEFAULT_READ_BACKEND).await?;
+    Ok(value.unwrap_or_default())
 }
 
-pub async fn do_async_put(key: &str, val: &str) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let _ = client.put(key.to_owned(), val.to_owned()).await?;
-    Ok("OK".into())
+#[derive(Clone, Copy, PartialEq)]
+pub enum PutCondition {
+    Always,
+    // NX
+    NotExists,
+    // XX
+    Exists,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Expiry {
+    // Drop any expiration the key had
+    Persist,
+    // KEEPTTL
+    Keep,
+    // Expire at the given Unix time in milliseconds
+    At(u64),
+}
+
+#[derive(Clone, Copy)]
+pub struct PutOptions {
+    pub condition: PutCondition,
+    pub expiry: Expiry,
+}
+
+impl Default for PutOptions {
+    fn default() -> Self {
+        PutOptions {
+            condition: PutCondition::Always,
+            expiry: Expiry::Persist,
+        }
+    }
+}
+
+// Write a string following SET semantics inside `txn`, replacing a value
+// of any type. Returns false when the NX/XX condition kept the value from
+// being written.
+pub async fn put_string(txn: &mut Transaction, key: &[u8], value: Vec<u8>, opts: PutOptions) -> Result<bool, Error> {
+    let meta_key = encode_meta_key(key);
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let meta = found.get(&meta_key);
+    let old_type = live_type(found.contains_key(key), meta, now_millis());
+    match opts.condition {
+        PutCondition::NotExists if old_type.is_some() => return Ok(false),
+        PutCondition::Exists if old_type.is_none() => return Ok(false),
+        _ => {},
+    }
+    let expire_at = match opts.expiry {
+        Expiry::At(expire_at) => expire_at,
+        Expiry::Keep if old_type.is_some() => meta.and_then(|raw| decode_meta(raw)).map_or(0, |m| m.expire_at),
+        _ => 0,
+    };
+    if let Some(data_type) = old_type.filter(|t| *t != DataType::String) {
+        delete_data(txn, key, data_type).await?;
+    }
+    write_string(txn, key, value, expire_at).await?;
+    Ok(true)
+}
+
+// Replies OK, or nil when NX/XX skipped the write
+pub async fn do_async_put(key: &str, val: &str, opts: PutOptions) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = put_string(&mut txn, key.as_bytes(), val.as_bytes().to_vec(), opts).await;
+    if finish_txn(txn, res).await? {
+        wake_key(key.as_bytes());
+        Ok("OK".into())
+    } else {
+        Ok(RedisValue::Null)
+    }
+}
+
+// 1 if the key was set, 0 if it already existed
+pub async fn do_async_setnx(key: &str, val: &str) -> Result<RedisValue, Error> {
+    let opts = PutOptions { condition: PutCondition::NotExists, ..PutOptions::default() };
+    let mut txn = begin_txn().await?;
+    let res = put_string(&mut txn, key.as_bytes(), val.as_bytes().to_vec(), opts).await;
+    let written = finish_txn(txn, res).await?;
+    if written {
+        wake_key(key.as_bytes());
+    }
+    Ok((written as i64).into())
+}
+
+async fn getset(txn: &mut Transaction, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+    let (old, _) = read_string(Reader::Txn(txn), key).await?;
+    write_string(txn, key, value, 0).await?;
+    Ok(old)
+}
+
+// Replace the value and reply the previous one (nil if there was none)
+pub async fn do_async_getset(key: &str, val: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = getset(&mut txn, key.as_bytes(), val.as_bytes().to_vec()).await;
+    let old = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(old.into())
+}
+
+async fn getex(txn: &mut Transaction, key: &[u8], expiry: Expiry) -> Result<Option<Vec<u8>>, Error> {
+    let (value, meta) = read_string(Reader::Txn(txn), key).await?;
+    if value.is_none() {
+        return Ok(None);
+    }
+    // Only the meta entry changes, the value is left alone
+    let meta_key = encode_meta_key(key);
+    match expiry {
+        Expiry::At(expire_at) => txn.put(meta_key, encode_meta(&KeyMeta::new(DataType::String, expire_at))).await?,
+        Expiry::Persist if meta.is_some() => txn.delete(meta_key).await?,
+        _ => {},
+    }
+    Ok(value)
+}
+
+// Reply the value and change its expiration, Keep leaves it untouched
+pub async fn do_async_getex(key: &str, expiry: Expiry) -> Result<RedisValue, Error> {
+    if expiry == Expiry::Keep {
+        return do_async_get(key, DEFAULT_READ_BACKEND).await;
+    }
+    let mut txn = begin_txn().await?;
+    let res = getex(&mut txn, key.as_bytes(), expiry).await;
+    let value = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(value.into())
+}
+
+// Read-modify-write of a string inside `txn`, keeping its expiration.
+// `update` gets the current value (None when missing) and returns the new
+// value together with what the command replies.
+pub async fn modify_string<T, F>(txn: &mut Transaction, key: &[u8], update: F) -> Result<T, Error>
+where
+    F: FnOnce(Option<Vec<u8>>) -> Result<(Vec<u8>, T), Error>,
+{
+    let (old, meta) = read_string(Reader::Txn(txn), key).await?;
+    let (value, reply) = update(old)?;
+    write_string(txn, key, value, meta.map_or(0, |m| m.expire_at)).await?;
+    Ok(reply)
+}
+
+// Append to the value (creating it if missing), replies the new length
+pub async fn do_async_append(key: &str, val: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = modify_string(&mut txn, key.as_bytes(), |old| {
+        let mut value = old.unwrap_or_default();
+        value.extend_from_slice(val.as_bytes());
+        let len = value.len() as i64;
+        Ok((value, len))
+    }).await;
+    let len = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(len.into())
+}
+
+// Overwrite the value from `offset` on, zero-padding it when it is shorter
+// than `offset`. Replies the new length.
+pub async fn do_async_setrange(key: &str, offset: usize, val: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = modify_string(&mut txn, key.as_bytes(), |old| {
+        let mut value = old.unwrap_or_default();
+        let end = offset + val.len();
+        if value.len() < end {
+            value.resize(end, 0);
+        }
+        value[offset..end].copy_from_slice(val.as_bytes());
+        let len = value.len() as i64;
+        Ok((value, len))
+    }).await;
+    let len = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(len.into())
+}
+
+fn not_integer() -> Error {
+    tikv_client::Error::StringError(String::from("ERR value is not an integer or out of range"))
+}
+
+// Add `delta` to the integer stored at the key (0 when missing), replies
+// the new value
+pub async fn do_async_incr_by(key: &str, delta: i64) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = modify_string(&mut txn, key.as_bytes(), |old| {
+        let current = match old {
+            Some(raw) => std::str::from_utf8(&raw).ok().and_then(|v| v.parse::<i64>().ok()).ok_or_else(not_integer)?,
+            None => 0,
+        };
+        let value = current.checked_add(delta).ok_or_else(|| {
+            tikv_client::Error::StringError(String::from("ERR increment or decrement would overflow"))
+        })?;
+        Ok((value.to_string().into_bytes(), value))
+    }).await;
+    let value = finish_txn(txn, res).await?;
+    wake_key(key.as_bytes());
+    Ok(value.into())
+}
+
+async fn delete_keys(txn: &mut Transaction, keys: &[Vec<u8>]) -> Result<(), Error> {
+    let mut wanted = keys.to_vec();
+    wanted.extend(keys.iter().map(|k| encode_meta_key(k)));
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let now = now_millis();
+    // Keep a time-stamped copy of every existing string before deleting it.
+    // The trash holds a single value per key, so collections are deleted
+    // for good.
+    let trash = get_config().trash_retention > 0;
+    for key in keys.iter() {
+        let meta = found.get(&encode_meta_key(key));
+        if trash && live_type(found.contains_key(key), meta, now) == Some(DataType::String) {
+            let value = encode_trash_value(now, &found[key]);
+            txn.put(encode_trash_key(key), value).await?;
+        }
+        // Expired collections are cleared too, they read as missing but
+        // their entries are still there
+        match stored_type(found.contains_key(key), meta) {
+            Some(DataType::String) | None => {},
+            Some(data_type) => delete_data(txn, key, data_type).await?,
+        }
+        delete_string(txn, key).await?;
+    }
+    Ok(())
 }
 
 pub async fn do_async_batch_del(keys: Vec<String>) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let _ = client.batch_delete(keys).await?;
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = delete_keys(&mut txn, &keys).await;
+    finish_txn(txn, res).await?;
+    keys.iter().for_each(|key| wake_key(key));
     Ok("OK".into())
 }
 
-pub async fn do_async_scan(prefix: &str, limit: u64) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let range = prefix.to_owned()..;
-    let result = client.scan(range, limit as u32).await?;
-    let values: Vec<_> = result.into_iter().map(|p| Vec::from([Into::<Vec<u8>>::into(p.key().clone()), Into::<Vec<u8>>::into(p.value().clone())])).collect();
-    Ok(values.into())
+async fn restore_key(txn: &mut Transaction, key: &[u8], replace: bool) -> Result<bool, Error> {
+    let trash_key = encode_trash_key(key);
+    let raw = match txn.get(trash_key.clone()).await? {
+        Some(raw) => raw,
+        None => return Ok(false),
+    };
+    let value = match decode_trash_value(&raw) {
+        Some((_, value)) => value.to_vec(),
+        None => return Err(tikv_client::Error::StringError(String::from("Corrupted trash entry"))),
+    };
+    if !replace && keys_exist(Reader::Txn(txn), &[key.to_vec()]).await?[0] {
+        return Err(tikv_client::Error::StringError(String::from("Target key name already exists")));
+    }
+    drop_other_types(txn, &[key.to_vec()]).await?;
+    write_string(txn, key, value, 0).await?;
+    txn.delete(trash_key).await?;
+    Ok(true)
 }
 
-pub async fn do_async_scan_range(start_key: &str, end_key: &str, limit: u64) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let range = start_key.to_owned()..end_key.to_owned();
-    let result = client.scan(range, limit as u32).await?;
-    let values: Vec<_> = result.into_iter().map(|p| Vec::from([Into::<Vec<u8>>::into(p.key().clone()), Into::<Vec<u8>>::into(p.value().clone())])).collect();
+pub async fn do_async_restore_key(key: &str, replace: bool) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = restore_key(&mut txn, key.as_bytes(), replace).await;
+    let restored = finish_txn(txn, res).await?;
+    if restored {
+        wake_key(key.as_bytes());
+    }
+    Ok((restored as i64).into())
+}
+
+const PURGE_BATCH: u32 = 1000;
+
+// Scan one page of [start, end) and delete, for every entry, the keys
+// `stale` returns. Gives back how many entries were stale and where the
+// next page starts, if there is one.
+async fn purge_page<F>(txn: &mut Transaction, start: Vec<u8>, end: Vec<u8>, stale: &F) -> Result<(u64, Option<Vec<u8>>), Error>
+where
+    F: Fn(&[u8], &[u8]) -> Vec<Vec<u8>>,
+{
+    let pairs: Vec<KvPair> = txn.scan(start..end, PURGE_BATCH).await?.collect();
+    let mut purged = 0;
+    for p in pairs.iter() {
+        let key = Into::<Vec<u8>>::into(p.key().to_owned());
+        let keys = stale(&key, p.value());
+        if !keys.is_empty() {
+            purged += 1;
+        }
+        for key in keys {
+            txn.delete(key).await?;
+        }
+    }
+    let next = match pairs.last() {
+        Some(last) if pairs.len() == PURGE_BATCH as usize => {
+            let mut next = Into::<Vec<u8>>::into(last.key().to_owned());
+            next.push(0);
+            Some(next)
+        },
+        _ => None,
+    };
+    Ok((purged, next))
+}
+
+// Walk an internal namespace one transaction per page, see purge_page
+async fn purge_namespace<F>(range: (Vec<u8>, Vec<u8>), stale: F) -> Result<u64, Error>
+where
+    F: Fn(&[u8], &[u8]) -> Vec<Vec<u8>>,
+{
+    let (mut start, end) = range;
+    let mut purged = 0;
+    loop {
+        let mut txn = begin_txn().await?;
+        let res = purge_page(&mut txn, start.clone(), end.clone(), &stale).await;
+        let (count, next) = finish_txn(txn, res).await?;
+        purged += count;
+        match next {
+            Some(next) => start = next,
+            None => return Ok(purged),
+        }
+    }
+}
+
+// Delete trash entries older than the retention window, returns how many
+pub async fn do_async_purge_trash() -> Result<u64, Error> {
+    let retention = get_config().trash_retention;
+    if retention == 0 {
+        return Ok(0);
+    }
+    let deadline = now_millis().saturating_sub(retention * 1000);
+    purge_namespace(trash_range(), |key, value| match decode_trash_value(value) {
+        Some((deleted_at, _)) if deleted_at <= deadline => vec![key.to_vec()],
+        _ => Vec::new(),
+    }).await
+}
+
+// One page of the meta namespace: delete every expired key with its meta
+// entry and whatever its type stores. Gives back how many keys expired and
+// where the next page starts, if there is one.
+async fn purge_expired_page(txn: &mut Transaction, start: Vec<u8>, end: Vec<u8>) -> Result<(u64, Option<Vec<u8>>), Error> {
+    let now = now_millis();
+    let entries = Reader::Txn(txn).scan((start..end).into(), PURGE_BATCH).await?;
+    let mut purged = 0;
+    for p in entries.iter() {
+        let meta = match decode_meta(p.value()) {
+            Some(meta) if meta.is_expired(now) => meta,
+            _ => continue,
+        };
+        let meta_key = Into::<Vec<u8>>::into(p.key().to_owned());
+        delete_data(txn, decode_meta_key(&meta_key), meta.data_type).await?;
+        txn.delete(meta_key).await?;
+        purged += 1;
+    }
+    let next = match entries.last() {
+        Some(last) if entries.len() == PURGE_BATCH as usize => {
+            let mut next = Into::<Vec<u8>>::into(last.key().to_owned());
+            next.push(0);
+            Some(next)
+        },
+        _ => None,
+    };
+    Ok((purged, next))
+}
+
+// Delete expired keys of every type, one transaction per page, returns how
+// many
+pub async fn do_async_purge_expired() -> Result<u64, Error> {
+    let (mut start, end) = meta_range();
+    let mut purged = 0;
+    loop {
+        let mut txn = begin_txn().await?;
+        let res = purge_expired_page(&mut txn, start.clone(), end.clone()).await;
+        let (count, next) = finish_txn(txn, res).await?;
+        purged += count;
+        match next {
+            Some(next) => start = next,
+            None => return Ok(purged),
+        }
+    }
+}
+
+const SCAN_PAGE_SIZE: u32 = 256;
+
+// Leave out pairs whose key has expired but was not purged yet
+async fn drop_expired(snapshot: &mut Snapshot, pairs: Vec<KvPair>) -> Result<Vec<KvPair>, Error> {
+    if pairs.is_empty() {
+        return Ok(pairs);
+    }
+    let meta_keys: Vec<Vec<u8>> = pairs.iter().map(|p| encode_meta_key(&Into::<Vec<u8>>::into(p.key().to_owned()))).collect();
+    let found = Reader::Snapshot(snapshot).batch_get(meta_keys.clone()).await?;
+    let now = now_millis();
+    let mut meta_keys = meta_keys.into_iter();
+    Ok(pairs.into_iter().filter(|_| {
+        let meta = meta_keys.next().and_then(|k| found.get(&k).and_then(|raw| decode_meta(raw)));
+        !meta.is_some_and(|m| m.is_expired(now))
+    }).collect())
+}
+
+// Scan [start, end) for up to `limit` pairs. With a time budget (ms) the scan
+// goes page by page and stops once the budget is spent, returning the pairs
+// read so far together with the key to continue from.
+async fn scan_pairs(snapshot: &mut Snapshot, start: Vec<u8>, end: Option<Vec<u8>>, limit: u32, budget: u64) -> Result<(Vec<KvPair>, Option<Vec<u8>>), Error> {
+    let page_size = if budget == 0 { limit } else { SCAN_PAGE_SIZE.min(limit) };
+    let started = Instant::now();
+    let mut pairs: Vec<KvPair> = Vec::new();
+    let mut start = user_range_start(&start);
+    loop {
+        let want = page_size.min(limit - pairs.len() as u32);
+        let range: BoundRange = match &end {
+            Some(end) => (start.clone()..end.clone()).into(),
+            None => (start.clone()..).into(),
+        };
+        let page: Vec<KvPair> = snapshot.scan(range, want).await?.collect();
+        let exhausted = (page.len() as u32) < want;
+        if let Some(last) = page.last() {
+            start = Into::<Vec<u8>>::into(last.key().to_owned());
+            start.push(0);
+        }
+        let page = drop_expired(snapshot, page).await?;
+        pairs.extend(page);
+        if exhausted || pairs.len() as u32 >= limit {
+            return Ok((pairs, None));
+        }
+        if budget > 0 && started.elapsed() >= Duration::from_millis(budget) {
+            return Ok((pairs, Some(start)));
+        }
+    }
+}
+
+// Without a time budget the reply is just the rows. With one it is
+// [cursor, truncated, rows], cursor being "" once the range is exhausted.
+fn scan_reply(rows: Vec<RedisValue>, cursor: Option<Vec<u8>>, budget: u64) -> RedisValue {
+    if budget == 0 {
+        return RedisValue::Array(rows);
+    }
+    let truncated = cursor.is_some() as i64;
+    RedisValue::Array(vec![
+        cursor.unwrap_or_default().into(),
+        truncated.into(),
+        RedisValue::Array(rows),
+    ])
+}
+
+// Reply of tikv.hgetall and tikv.smembers once max-reply-size applies:
+// [cursor, truncated, rows], cursor being the hex encoded field or member
+// to continue from, or "0" once everything was returned
+pub fn paged_reply(rows: RedisValue, next: Option<Vec<u8>>) -> RedisValue {
+    let truncated = next.is_some() as i64;
+    let cursor: RedisValue = match next {
+        Some(next) => to_hex(&next).into(),
+        None => "0".into(),
+    };
+    RedisValue::Array(vec![cursor, truncated.into(), rows])
+}
+
+fn pair_rows(pairs: Vec<KvPair>) -> Vec<RedisValue> {
+    pairs.into_iter().map(|p| Vec::from([Into::<Vec<u8>>::into(p.key().clone()), Into::<Vec<u8>>::into(p.value().clone())]).into()).collect()
+}
+
+pub async fn do_async_scan(prefix: &str, limit: u64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let budget = get_config().scan_time_budget;
+    let mut snapshot = get_snapshot(backend).await?;
+    let (pairs, cursor) = scan_pairs(&mut snapshot, prefix.as_bytes().to_vec(), None, limit as u32, budget).await?;
+    Ok(scan_reply(pair_rows(pairs), cursor, budget))
+}
+
+pub async fn do_async_scan_range(start_key: &str, end_key: &str, limit: u64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let budget = get_config().scan_time_budget;
+    let mut snapshot = get_snapshot(backend).await?;
+    let (pairs, cursor) = scan_pairs(&mut snapshot, start_key.as_bytes().to_vec(), Some(end_key.as_bytes().to_vec()), limit as u32, budget).await?;
+    Ok(scan_reply(pair_rows(pairs), cursor, budget))
+}
+
+pub async fn do_async_scan_values(start_key: &str, end_key: Option<&str>, limit: u64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let budget = get_config().scan_time_budget;
+    let end_key = end_key.map(|k| k.as_bytes().to_vec());
+    let mut snapshot = get_snapshot(backend).await?;
+    let (pairs, cursor) = scan_pairs(&mut snapshot, start_key.as_bytes().to_vec(), end_key, limit as u32, budget).await?;
+    let values: Vec<RedisValue> = pairs.into_iter().map(|p| Into::<Vec<u8>>::into(p.into_value()).into()).collect();
+    Ok(scan_reply(values, cursor, budget))
+}
+
+const COUNT_PAGE_SIZE: u32 = 10240;
+
+// Exact number of keys starting with `prefix`, scanning keys only. Expired
+// strings still waiting for the background purge are found through their
+// meta entries and subtracted. Collections keep no entry under the key
+// itself, so they are not counted and their meta entries are skipped.
+pub async fn do_async_count(prefix: &str) -> Result<RedisValue, Error> {
+    let mut snapshot = get_snapshot(ReadBackend::Txn).await?;
+    let mut reader = Reader::Snapshot(&mut snapshot);
+    let end = prefix_end(prefix.as_bytes());
+    let mut start = user_range_start(prefix.as_bytes());
+    let mut count: i64 = 0;
+    loop {
+        let range: BoundRange = match &end {
+            Some(end) => (start.clone()..end.clone()).into(),
+            None => (start.clone()..).into(),
+        };
+        let keys = reader.scan_keys(range, COUNT_PAGE_SIZE).await?;
+        count += keys.len() as i64;
+        match keys.last() {
+            Some(last) if keys.len() as u32 == COUNT_PAGE_SIZE => {
+                start = Into::<Vec<u8>>::into(last.to_owned());
+                start.push(0);
+            },
+            _ => break,
+        }
+    }
+
+    let mut start = encode_meta_key(prefix.as_bytes());
+    let end = match &end {
+        Some(end) => encode_meta_key(end),
+        None => meta_range().1,
+    };
+    let now = now_millis();
+    loop {
+        let pairs = reader.scan((start.clone()..end.clone()).into(), COUNT_PAGE_SIZE).await?;
+        count -= pairs.iter().filter(|p| decode_meta(p.value()).is_some_and(|m| m.data_type == DataType::String && m.is_expired(now))).count() as i64;
+        match pairs.last() {
+            Some(last) if pairs.len() as u32 == COUNT_PAGE_SIZE => {
+                start = Into::<Vec<u8>>::into(last.key().to_owned());
+                start.push(0);
+            },
+            _ => return Ok(count.into()),
+        }
+    }
+}
+
+// Scan several ranges from the same snapshot, replying one group of rows
+// per range in argument order
+pub async fn do_async_batch_scan(ranges: Vec<(String, String)>, each_limit: u64, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let mut snapshot = get_snapshot(backend).await?;
+    let mut values: Vec<RedisValue> = Vec::new();
+    for (start, end) in ranges.into_iter() {
+        let (pairs, _) = scan_pairs(&mut snapshot, start.into_bytes(), Some(end.into_bytes()), each_limit as u32, 0).await?;
+        values.push(RedisValue::Array(pair_rows(pairs)));
+    }
     Ok(values.into())
 }
 
+// One page of keys of every type in [start, end) (no end for the whole
+// keyspace), in key order, each with whether it has an entry under the key
+// itself and its raw meta entry. Strings live in the user keyspace, the
+// other types and expiring strings in the meta namespace, so both are
+// scanned for up to `count` keys and merged up to the first key either scan
+// may have stopped short of. Also returns the key to continue from, None
+// once both are exhausted.
+pub async fn key_page(reader: &mut Reader<'_>, start: &[u8], end: Option<&[u8]>, count: u32) -> Result<(Vec<(Vec<u8>, bool, Option<Vec<u8>>)>, Option<Vec<u8>>), Error> {
+    let data_range: BoundRange = match end {
+        Some(end) => (user_range_start(start)..end.to_vec()).into(),
+        None => (user_range_start(start)..).into(),
+    };
+    let meta_end = match end {
+        Some(end) => encode_meta_key(end),
+        None => meta_range().1,
+    };
+    let data_keys: Vec<Vec<u8>> = reader.scan_keys(data_range, count).await?.into_iter().map(Into::into).collect();
+    let metas = reader.scan((encode_meta_key(&user_range_start(start))..meta_end).into(), count).await?;
+    let metas: BTreeMap<Vec<u8>, Vec<u8>> = metas.into_iter()
+        .map(|p| (decode_meta_key(&Into::<Vec<u8>>::into(p.key().to_owned())).to_vec(), p.into_value()))
+        .collect();
+
+    // A full scan may have left out keys after its last one, so the page
+    // stops there
+    let mut limit: Option<Vec<u8>> = None;
+    if data_keys.len() == count as usize {
+        limit = data_keys.last().cloned();
+    }
+    if let Some(last) = metas.keys().next_back().filter(|_| metas.len() == count as usize) {
+        if limit.as_ref().map_or(true, |limit| last < limit) {
+            limit = Some(last.clone());
+        }
+    }
+    let data: BTreeSet<Vec<u8>> = data_keys.into_iter().collect();
+    let mut keys = data.clone();
+    keys.extend(metas.keys().cloned());
+    let keys: Vec<Vec<u8>> = keys.into_iter()
+        .filter(|k| limit.as_ref().map_or(true, |limit| k <= limit))
+        .take(count as usize)
+        .collect();
+    let next = match keys.last() {
+        Some(last) if limit.is_some() || keys.len() == count as usize => {
+            let mut next = last.clone();
+            next.push(0);
+            Some(next)
+        },
+        _ => None,
+    };
+    let page = keys.into_iter().map(|k| {
+        let has_data = data.contains(&k);
+        let meta = metas.get(&k).cloned();
+        (k, has_data, meta)
+    }).collect();
+    Ok((page, next))
+}
+
+// One SCAN step over keys of every type: up to `count` keys from `cursor`
+// on (None starts at the first key), keeping the live ones matching
+// `pattern` and, with `filter`, of that type, read from the type tag of
+// their meta entries. The reply cursor is the key to continue from in hex,
+// or "0" once the keyspace is exhausted, as in do_async_sscan.
+// How a tikv.scankeys iteration picks its snapshot: a new one per call, a
+// new pinned one (SNAPSHOT on cursor 0), or the one pinned under an id the
+// cursor carries
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScanSnapshot {
+    Fresh,
+    Pin,
+    Pinned(u64),
+}
+
+pub async fn do_async_scan_keys(cursor: Option<Vec<u8>>, pattern: Option<Vec<u8>>, filter: Option<DataType>, count: u32, pin: ScanSnapshot, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let pinned = match pin {
+        ScanSnapshot::Fresh => None,
+        ScanSnapshot::Pin => Some(pin_scan_snapshot().await?),
+        ScanSnapshot::Pinned(id) => Some(id),
+    };
+    let backend = match pinned {
+        Some(id) => ReadBackend::Pinned(scan_snapshot_version(id)?),
+        None => backend,
+    };
+    let mut snapshot = get_snapshot(backend).await?;
+    let start = cursor.unwrap_or_default();
+    let (page, next) = key_page(&mut Reader::Snapshot(&mut snapshot), &start, None, count).await?;
+    let now = now_millis();
+    let keys: Vec<RedisValue> = page.into_iter()
+        .filter(|(key, has_data, meta)| {
+            let data_type = live_type(*has_data, meta.as_ref(), now);
+            data_type.is_some() && (filter.is_none() || data_type == filter)
+                && pattern.as_ref().map_or(true, |pattern| glob_match(pattern, key))
+        })
+        .map(|(key, _, _)| key.into())
+        .collect();
+    let next: RedisValue = match (next, pinned) {
+        (Some(next), Some(id)) => format!("{}:{}", id, to_hex(&next)).into(),
+        (Some(next), None) => to_hex(&next).into(),
+        (None, pinned) => {
+            if let Some(id) = pinned {
+                release_scan_snapshot(id);
+            }
+            "0".into()
+        },
+    };
+    Ok(RedisValue::Array(vec![next, RedisValue::Array(keys)]))
+}
+
+// One step over the keys with an expiration: up to `count` meta entries
+// from `cursor` on, keeping the keys whose remaining time to live in ms is
+// within [min_ttl, max_ttl], each followed by that time. There is no index
+// ordered by expiration, so every meta entry is looked at once over the
+// whole iteration. The cursor works like do_async_scan_keys.
+pub async fn do_async_ttl_scan(cursor: Option<Vec<u8>>, min_ttl: u64, max_ttl: u64, count: u32, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let mut snapshot = get_snapshot(backend).await?;
+    let (first, end) = meta_range();
+    let start = match cursor {
+        Some(key) => encode_meta_key(&key),
+        None => first,
+    };
+    let pairs = Reader::Snapshot(&mut snapshot).scan((start..end).into(), count).await?;
+    let exhausted = pairs.len() < count as usize;
+    let now = now_millis();
+    let mut last = None;
+    let mut entries: Vec<RedisValue> = Vec::new();
+    for p in pairs.into_iter() {
+        let key = decode_meta_key(&Into::<Vec<u8>>::into(p.key().to_owned())).to_vec();
+        if let Some(meta) = decode_meta(p.value()) {
+            let ttl = meta.expire_at.saturating_sub(now);
+            if meta.expire_at != 0 && !meta.is_expired(now) && min_ttl <= ttl && ttl <= max_ttl {
+                entries.push(key.clone().into());
+                entries.push((ttl as i64).into());
+            }
+        }
+        last = Some(key);
+    }
+    let next: RedisValue = match last {
+        Some(mut next) if !exhausted => {
+            next.push(0);
+            to_hex(&next).into()
+        },
+        _ => "0".into(),
+    };
+    Ok(RedisValue::Array(vec![next, RedisValue::Array(entries)]))
+}
+
+const DELETE_RANGE_BATCH: u32 = 1000;
+
+async fn delete_range_page(txn: &mut Transaction, start: Vec<u8>, end: Vec<u8>) -> Result<Vec<Vec<u8>>, Error> {
+    let keys: Vec<Vec<u8>> = txn.scan_keys(start..end, DELETE_RANGE_BATCH).await?.map(|k| k.into()).collect();
+    for key in keys.iter() {
+        delete_string(txn, key).await?;
+    }
+    Ok(keys)
+}
+
+// Delete every key in [start, end), one transaction per page of keys.
+// Returns how many keys were deleted.
 pub async fn do_async_delete_range(key_start: &str, key_end: &str) -> Result<RedisValue, Error> {
+    let end = key_end.as_bytes().to_vec();
+    let mut start = user_range_start(key_start.as_bytes());
+    let mut deleted: i64 = 0;
+    loop {
+        let mut txn = begin_txn().await?;
+        let res = delete_range_page(&mut txn, start.clone(), end.clone()).await;
+        let keys = finish_txn(txn, res).await?;
+        deleted += keys.len() as i64;
+        match keys.last() {
+            Some(last) if keys.len() as u32 == DELETE_RANGE_BATCH => {
+                start = last.clone();
+                start.push(0);
+            },
+            _ => return Ok(deleted.into()),
+        }
+    }
+}
+
+const MIGRATE_BATCH: u32 = 1000;
+
+// Copy raw pairs into the transactional keyspace, skipping keys that
+// already hold a value there. Returns how many were copied.
+async fn migrate_page(txn: &mut Transaction, pairs: &[KvPair]) -> Result<u64, Error> {
+    let keys: Vec<Vec<u8>> = pairs.iter().map(|p| Into::<Vec<u8>>::into(p.key().to_owned())).collect();
+    let exists = keys_exist(Reader::Txn(txn), &keys).await?;
+    let mut copied = 0;
+    for ((key, p), exists) in keys.iter().zip(pairs.iter()).zip(exists) {
+        if !exists {
+            write_string(txn, key, p.value().clone(), 0).await?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+// Copy the keys in [start, end) from the raw keyspace, where versions that
+// wrote through the RawClient stored them, into the transactional keyspace
+// every command reads now. One transaction per page of keys. Keys already
+// written through transactions win, and the raw copies are left in place.
+// Returns how many keys were copied.
+pub async fn do_async_migrate_raw(key_start: &str, key_end: Option<&str>) -> Result<RedisValue, Error> {
     let client = get_client()?;
-    let range = key_start.to_owned()..key_end.to_owned();
-    let result = client.delete_range(range).await?;
-    Ok(result.into())
+    let end = key_end.map(|k| k.as_bytes().to_vec());
+    let mut start = user_range_start(key_start.as_bytes());
+    let mut copied: i64 = 0;
+    loop {
+        let range: BoundRange = match &end {
+            Some(end) => (start.clone()..end.clone()).into(),
+            None => (start.clone()..).into(),
+        };
+        let pairs = client.scan(range, MIGRATE_BATCH).await?;
+        let mut txn = begin_txn().await?;
+        let res = migrate_page(&mut txn, &pairs).await;
+        copied += finish_txn(txn, res).await? as i64;
+        match pairs.last() {
+            Some(last) if pairs.len() == MIGRATE_BATCH as usize => {
+                start = Into::<Vec<u8>>::into(last.key().to_owned());
+                start.push(0);
+            },
+            _ => return Ok(copied.into()),
+        }
+    }
 }
 
 pub async fn do_async_close() -> Result<RedisValue, Error> {
     let _ = get_client()?;
     *GLOBAL_CLIENT.write().unwrap() = None;
+    *GLOBAL_TXN_CLIENT.write().unwrap() = None;
     Ok("Closed".into())
 }
 
-pub async fn do_async_batch_get(keys: Vec<String>) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let result = client.batch_get(keys.iter().map(|k| {Key::from(k.to_owned())})).await?;
-    let mut kvret: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
-    result.into_iter().for_each(|p| {
-        let key = Into::<Vec<u8>>::into(p.key().to_owned());
-        let value = Into::<Vec<u8>>::into(p.value().to_owned());
-        kvret.insert(key, value);
-    });
-    let values: Vec<_> = keys.into_iter().map(|k| {
-        let data = kvret.get::<Vec<u8>>(&k.into());
-        match data {
-            Some(val) => {
-                Into::<RedisValue>::into(val.to_owned())
-            },
-            None => {
-                RedisValue::Null
-            }
-        }
-    }).collect();
+pub async fn do_async_batch_get(keys: Vec<String>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.into_bytes()).collect();
+    let values: Vec<RedisValue> = read_values(&keys, backend).await?.into_iter().map(|v| v.into()).collect();
     Ok(values.into())
 }
 
+// Whole value of each key whatever its type: a string's value, a hash's
+// fields and values, a list's elements, a set's members or a sorted set's
+// members and scores, nil for missing keys. One batch_get finds every
+// type, then each collection is scanned from the same snapshot.
+pub async fn do_async_mget_any(keys: Vec<String>, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.into_bytes()).collect();
+    let mut snapshot = get_snapshot(backend).await?;
+    let mut found = Reader::Snapshot(&mut snapshot).batch_get(keys.iter().flat_map(|k| type_keys(k)).collect()).await?;
+    let now = now_millis();
+    let mut replies = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        let meta = found.remove(&encode_meta_key(key));
+        let data = found.remove(key);
+        let reply = match live_type(data.is_some(), meta.as_ref(), now) {
+            None => RedisValue::Null,
+            Some(DataType::String) => data.into(),
+            Some(DataType::Hash) => fields_reply(read_fields(&mut Reader::Snapshot(&mut snapshot), key).await?),
+            Some(DataType::List) => {
+                let meta = meta.as_deref().and_then(decode_meta).ok_or_else(wrong_type)?;
+                let elements = read_list(Reader::Snapshot(&mut snapshot), key, &meta).await?;
+                RedisValue::Array(elements.into_iter().map(RedisValue::from).collect())
+            },
+            Some(DataType::Set) => {
+                let members = read_members(&mut Reader::Snapshot(&mut snapshot), key).await?;
+                RedisValue::Array(members.into_iter().map(RedisValue::from).collect())
+            },
+            Some(DataType::ZSet) => entries_reply(read_scores(&mut Reader::Snapshot(&mut snapshot), key).await?, true),
+        };
+        replies.push(reply);
+    }
+    Ok(RedisValue::Array(replies))
+}
+
+async fn put_strings(txn: &mut Transaction, kvs: Vec<KvPair>) -> Result<(), Error> {
+    let keys: Vec<Vec<u8>> = kvs.iter().map(|p| Into::<Vec<u8>>::into(p.key().to_owned())).collect();
+    drop_other_types(txn, &keys).await?;
+    for p in kvs.into_iter() {
+        let key = Into::<Vec<u8>>::into(p.key().to_owned());
+        write_string(txn, &key, p.into_value(), 0).await?;
+    }
+    Ok(())
+}
+
+// All pairs are written in one transaction, so readers see either none or
+// all of them. The Transaction of tikv-client 0.1.0 has no batch put API,
+// only RawClient does, so each pair is a put of its own. Puts are buffered
+// client side until the commit sends them together in the prewrite.
 pub async fn do_async_batch_put(kvs: Vec<KvPair>) -> Result<RedisValue, Error> {
-    let client = get_client()?;
-    let _ = client.batch_put(kvs).await?;
+    let mut txn = begin_txn().await?;
+    let keys: Vec<Vec<u8>> = kvs.iter().map(|p| Into::<Vec<u8>>::into(p.key().to_owned())).collect();
+    let res = put_strings(&mut txn, kvs).await;
+    finish_txn(txn, res).await?;
+    keys.iter().for_each(|key| wake_key(key));
     Ok("OK".into())
 }
+
+async fn put_strings_nx(txn: &mut Transaction, kvs: Vec<KvPair>) -> Result<bool, Error> {
+    let keys: Vec<Vec<u8>> = kvs.iter().map(|p| Into::<Vec<u8>>::into(p.key().to_owned())).collect();
+    if keys_exist(Reader::Txn(txn), &keys).await?.into_iter().any(|exists| exists) {
+        return Ok(false);
+    }
+    put_strings(txn, kvs).await?;
+    Ok(true)
+}
+
+// Write all pairs only if none of the keys exists, replies 1 or 0
+pub async fn do_async_batch_put_nx(kvs: Vec<KvPair>) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let keys: Vec<Vec<u8>> = kvs.iter().map(|p| Into::<Vec<u8>>::into(p.key().to_owned())).collect();
+    let res = put_strings_nx(&mut txn, kvs).await;
+    let written = finish_txn(txn, res).await?;
+    if written {
+        keys.iter().for_each(|key| wake_key(key));
+    }
+    Ok((written as i64).into())
+}
+
+async fn cas(txn: &mut Transaction, key: &[u8], expected: &[u8], value: Vec<u8>) -> Result<(bool, Option<Vec<u8>>), Error> {
+    let (previous, meta) = read_string(Reader::Txn(txn), key).await?;
+    if previous.as_deref() != Some(expected) {
+        return Ok((false, previous));
+    }
+    write_string(txn, key, value, meta.map_or(0, |m| m.expire_at)).await?;
+    Ok((true, previous))
+}
+
+// Set the value only if the key holds a string equal to `expected`,
+// keeping its expiration. The read and the write share one transaction, so
+// a concurrent writer makes the commit fail instead of being overwritten.
+// A missing or expired key never matches, another type is WRONGTYPE.
+// Replies [swapped, previous value].
+pub async fn do_async_cas(key: &str, expected: &str, val: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = cas(&mut txn, key.as_bytes(), expected.as_bytes(), val.as_bytes().to_vec()).await;
+    let (swapped, previous) = finish_txn(txn, res).await?;
+    if swapped {
+        wake_key(key.as_bytes());
+    }
+    Ok(RedisValue::Array(vec![(swapped as i64).into(), previous.into()]))
+}
+
+// Find the first bit set to `bit` in `data`, following Redis BITPOS rules:
+// start/end are byte offsets (or bit offsets when `bit_unit` is set) and may
+// be negative, and a missing clear bit with no explicit end reports the
+// position right after the range, as if the value were padded with zeros.
+fn bitpos(data: &[u8], bit: u8, start: Option<i64>, end: Option<i64>, bit_unit: bool) -> i64 {
+    if data.is_empty() {
+        return if bit == 1 { -1 } else { 0 };
+    }
+    let total = if bit_unit { data.len() as i64 * 8 } else { data.len() as i64 };
+    let mut first = start.unwrap_or(0);
+    let mut last = end.unwrap_or(total - 1);
+    if first < 0 {
+        first = (first + total).max(0);
+    }
+    if last < 0 {
+        last = (last + total).max(0);
+    }
+    if last >= total {
+        last = total - 1;
+    }
+    if first > last {
+        return -1;
+    }
+    let (first_bit, last_bit) = if bit_unit { (first, last) } else { (first * 8, last * 8 + 7) };
+    let skip = if bit == 1 { 0x00 } else { 0xff };
+    let mut pos = first_bit;
+    while pos <= last_bit {
+        let byte = data[(pos / 8) as usize];
+        if pos % 8 == 0 && pos + 7 <= last_bit && byte == skip {
+            pos += 8;
+            continue;
+        }
+        if (byte >> (7 - pos % 8)) & 1 == bit {
+            return pos;
+        }
+        pos += 1;
+    }
+    if bit == 0 && end.is_none() {
+        return last_bit + 1;
+    }
+    -1
+}
+
+// There is no chunked bitmap encoding: string values are stored whole under
+// their key, as every other string command reads and writes them, so there
+// are no chunk keys to narrow the read to. The value is read with one point
+// get and searched in memory, which costs the same as tikv.get.
+pub async fn do_async_bitpos(key: &str, bit: u8, start: Option<i64>, end: Option<i64>, bit_unit: bool, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let value = read_value(key.as_bytes(), backend).await?.unwrap_or_default();
+    Ok(bitpos(&value, bit, start, end, bit_unit).into())
+}
+
+// Most cells the LCS table may have, (len1 + 1) * (len2 + 1). Each cell is
+// a u32, so this caps the table at 256 MB.
+const LCS_MAX_CELLS: usize = 1 << 26;
+
+// Longest common subsequence of `a` and `b`, returned together with the
+// matched ranges as (a_start, a_end, b_start, b_end), last match first.
+fn lcs(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<(usize, usize, usize, usize)>) {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut common: Vec<u8> = Vec::new();
+    let mut matches = Vec::new();
+    let mut range: Option<(usize, usize, usize, usize)> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            common.push(a[i - 1]);
+            range = match range {
+                Some((a_start, a_end, b_start, b_end)) if a_start == i && b_start == j => {
+                    Some((i - 1, a_end, j - 1, b_end))
+                },
+                Some(prev) => {
+                    matches.push(prev);
+                    Some((i - 1, i - 1, j - 1, j - 1))
+                },
+                None => Some((i - 1, i - 1, j - 1, j - 1)),
+            };
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            if let Some(prev) = range.take() {
+                matches.push(prev);
+            }
+        }
+    }
+    if let Some(prev) = range {
+        matches.push(prev);
+    }
+    common.reverse();
+    (common, matches)
+}
+
+pub async fn do_async_lcs(key1: &str, key2: &str, want_len: bool, want_idx: bool, min_match_len: u64, with_match_len: bool, backend: ReadBackend) -> Result<RedisValue, Error> {
+    // Read both values with a single batch_get so they are fetched together.
+    let mut values = read_values(&[key1.as_bytes().to_vec(), key2.as_bytes().to_vec()], backend).await?.into_iter();
+    let a = values.next().flatten().unwrap_or_default();
+    let b = values.next().flatten().unwrap_or_default();
+    if (a.len() + 1).saturating_mul(b.len() + 1) > LCS_MAX_CELLS {
+        return Err(tikv_client::Error::StringError(String::from("ERR values are too large for LCS")));
+    }
+
+    let (common, matches) = lcs(&a, &b);
+    if want_len {
+        return Ok(common.len().into());
+    }
+    if !want_idx {
+        return Ok(common.into());
+    }
+    let ranges: Vec<RedisValue> = matches.into_iter()
+        .filter(|(a_start, a_end, _, _)| (a_end - a_start + 1) as u64 >= min_match_len)
+        .map(|(a_start, a_end, b_start, b_end)| {
+            let mut item = vec![
+                RedisValue::Array(vec![a_start.into(), a_end.into()]),
+                RedisValue::Array(vec![b_start.into(), b_end.into()]),
+            ];
+            if with_match_len {
+                item.push((a_end - a_start + 1).into());
+            }
+            RedisValue::Array(item)
+        }).collect();
+    Ok(RedisValue::Array(vec![
+        "matches".into(),
+        RedisValue::Array(ranges),
+        "len".into(),
+        common.len().into(),
+    ]))
+}
+
+async fn wait_key(cid: u64, key: &str, timeout_ms: u64) -> Result<RedisValue, Error> {
+    let deadline = wait_deadline(timeout_ms)?;
+    // Parked before the first read, so a write right after it wakes us
+    let waiter = park(cid, vec![key.as_bytes().to_vec()], false);
+    let initial = read_value(key.as_bytes(), ReadBackend::Txn).await?;
+    loop {
+        waiter.wait(deadline).await;
+        // Nobody is left to tell once the client disconnected
+        if !still_waiting(cid) {
+            return Ok(RedisValue::Null);
+        }
+        if read_value(key.as_bytes(), ReadBackend::Txn).await? != initial {
+            return Ok(1_i64.into());
+        }
+        if Instant::now() >= deadline {
+            return Ok(0_i64.into());
+        }
+    }
+}
+
+// Wait for client `cid` until the key's value differs from the one seen on
+// entry, parked on the key, see blocking.rs. Returns 1 when it changed and 0 when `timeout_ms` elapsed (0
+// waits up to BLOCKING_MAX_WAIT). Stops once the client disconnected. The
+// caller registers `cid` with start_waiting.
+pub async fn do_async_wait_key(cid: u64, key: &str, timeout_ms: u64) -> Result<RedisValue, Error> {
+    let res = wait_key(cid, key, timeout_ms).await;
+    stop_waiting(cid);
+    res
+}
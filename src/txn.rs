@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use tikv_client::{BoundRange, Error, Key, KvPair, Snapshot, Transaction};
+use crate::tikv::get_txn_client;
+
+// Read access shared by read-write transactions and read-only snapshots, so
+// the value decoding helpers work with either.
+pub enum Reader<'a> {
+    Txn(&'a mut Transaction),
+    Snapshot(&'a mut Snapshot),
+}
+
+pub fn pairs_to_map(pairs: impl IntoIterator<Item = KvPair>) -> HashMap<Vec<u8>, Vec<u8>> {
+    pairs.into_iter().map(|p| (Into::<Vec<u8>>::into(p.key().to_owned()), p.into_value())).collect()
+}
+
+impl Reader<'_> {
+    // Found keys and their values, missing keys are left out
+    pub async fn batch_get(&mut self, keys: Vec<Vec<u8>>) -> Result<HashMap<Vec<u8>, Vec<u8>>, Error> {
+        match self {
+            Reader::Txn(txn) => Ok(pairs_to_map(txn.batch_get(keys).await?)),
+            Reader::Snapshot(snapshot) => Ok(pairs_to_map(snapshot.batch_get(keys).await?)),
+        }
+    }
+
+    pub async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>, Error> {
+        match self {
+            Reader::Txn(txn) => Ok(txn.scan(range, limit).await?.collect()),
+            Reader::Snapshot(snapshot) => Ok(snapshot.scan(range, limit).await?.collect()),
+        }
+    }
+
+    pub async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>, Error> {
+        match self {
+            Reader::Txn(txn) => Ok(txn.scan_keys(range, limit).await?.collect()),
+            Reader::Snapshot(snapshot) => Ok(snapshot.scan_keys(range, limit).await?.collect()),
+        }
+    }
+}
+
+const DELETE_PAGE_SIZE: u32 = 1000;
+
+// Delete every key in [start, end) inside `txn`, page by page
+pub async fn delete_range(txn: &mut Transaction, range: (Vec<u8>, Vec<u8>)) -> Result<(), Error> {
+    let (mut start, end) = range;
+    loop {
+        let keys = Reader::Txn(txn).scan_keys((start.clone()..end.clone()).into(), DELETE_PAGE_SIZE).await?;
+        let count = keys.len();
+        for key in keys.into_iter() {
+            start = key.into();
+            txn.delete(start.clone()).await?;
+            start.push(0);
+        }
+        if count < DELETE_PAGE_SIZE as usize {
+            return Ok(());
+        }
+    }
+}
+
+pub async fn begin_txn() -> Result<Transaction, Error> {
+    get_txn_client()?.begin_optimistic().await
+}
+
+// Commit `txn` when the work done in it succeeded, roll it back otherwise
+pub async fn finish_txn<T>(mut txn: Transaction, res: Result<T, Error>) -> Result<T, Error> {
+    match res {
+        Ok(value) => {
+            txn.commit().await?;
+            Ok(value)
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        },
+    }
+}
@@ -1,8 +1,12 @@
 use std::future::Future;
-use redis_module::{RedisValue, ThreadSafeContext, BlockedClient };
+use std::time::{SystemTime, UNIX_EPOCH};
+use redis_module::{raw, Context, RedisString, RedisValue, ThreadSafeContext, BlockedClient };
 use std::sync::{RwLockReadGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-pub use crate::init::{ GLOBAL_RT1, GLOBAL_RT2, GLOBAL_COUNTER };
+use tokio::task::JoinHandle;
+
+pub use crate::init::{ GLOBAL_RT1, GLOBAL_RT2, GLOBAL_COUNTER, GLOBAL_INFLIGHT, GLOBAL_CLIENT_TASKS };
 
 // Respose for redis blocked client
 pub fn redis_resp<E>(client: BlockedClient, result: Result<RedisValue, E>)
@@ -21,8 +25,23 @@ where
     };
 }
 
-// Spawn async task from Redis Module main thread
-pub fn tokio_spawn<T>(future: T)
+// Counts a spawned task in GLOBAL_INFLIGHT until it finishes or is aborted
+struct Inflight;
+
+impl Inflight {
+    fn start() -> Inflight {
+        GLOBAL_INFLIGHT.fetch_add(1, Ordering::SeqCst);
+        Inflight
+    }
+}
+
+impl Drop for Inflight {
+    fn drop(&mut self) {
+        GLOBAL_INFLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn spawn_task<T>(future: T) -> JoinHandle<()>
 where
     T: Future + Send + 'static,
     T::Output: Send + 'static,
@@ -36,5 +55,209 @@ where
         tmp = GLOBAL_RT2.read().unwrap();
     }
     let hdl = tmp.as_ref().unwrap();
-    hdl.spawn(future);
+    let inflight = Inflight::start();
+    hdl.spawn(async move {
+        future.await;
+        drop(inflight);
+    })
+}
+
+// Spawn async task from Redis Module main thread
+pub fn tokio_spawn<T>(future: T)
+where
+    T: Future + Send + 'static,
+    T::Output: Send + 'static,
+{
+    spawn_task(future);
+}
+
+static TASK_ID: AtomicU64 = AtomicU64::new(0);
+
+// Spawn a task serving client `cid` that abort_client_tasks cancels when
+// the client disconnects. Only for tasks that do not write: a snapshot can
+// be dropped at any await point, an open transaction cannot, and a commit
+// cut short would leave locks behind for other readers to resolve.
+pub fn tokio_spawn_for<T>(cid: u64, future: T)
+where
+    T: Future + Send + 'static,
+    T::Output: Send + 'static,
+{
+    let id = TASK_ID.fetch_add(1, Ordering::Relaxed);
+    // Held until the handle is stored, so a task finishing right away
+    // cannot try to remove its entry before it exists
+    let mut tasks = GLOBAL_CLIENT_TASKS.lock().unwrap();
+    let handle = spawn_task(async move {
+        future.await;
+        let mut tasks = GLOBAL_CLIENT_TASKS.lock().unwrap();
+        if let Some(running) = tasks.get_mut(&cid) {
+            running.remove(&id);
+            if running.is_empty() {
+                tasks.remove(&cid);
+            }
+        }
+    });
+    tasks.entry(cid).or_default().insert(id, handle);
+}
+
+// Abort the tasks tokio_spawn_for started for client `cid`. Dropping a
+// task drops its blocked client, which unblocks it without a reply.
+pub fn abort_client_tasks(cid: u64) {
+    let running = GLOBAL_CLIENT_TASKS.lock().unwrap().remove(&cid);
+    for handle in running.into_iter().flat_map(|running| running.into_values()) {
+        handle.abort();
+    }
+}
+
+// Redis client ID of the connection running the current command
+pub fn client_id(ctx: &Context) -> u64 {
+    unsafe { raw::RedisModule_GetClientId.unwrap()(ctx.ctx) }
+}
+
+// ACL user name of the connection running the current command
+pub fn current_user(ctx: &Context) -> String {
+    let name = unsafe { raw::RedisModule_GetCurrentUserName.unwrap()(ctx.ctx) };
+    RedisString::from_redis_module_string(ctx.ctx, name).to_string()
+}
+
+// Wall clock time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// Lowercase hexadecimal form of `bytes`
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Bytes of a hexadecimal string as written by to_hex, None when it is not
+// valid hex
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+static RANDOM_STATE: AtomicU64 = AtomicU64::new(0);
+
+// Uniform random number in [0, n) for sampling commands, from a splitmix64
+// sequence seeded with the clock. Not suitable for anything secret.
+pub fn random_below(n: u64) -> u64 {
+    let mut seed = RANDOM_STATE.load(Ordering::Relaxed);
+    if seed == 0 {
+        seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64 | 1;
+    }
+    let next = seed.wrapping_add(0x9e3779b97f4a7c15);
+    RANDOM_STATE.store(next, Ordering::Relaxed);
+    let mut z = next;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    if n == 0 { 0 } else { z % n }
+}
+
+// Fisher-Yates shuffle driven by random_below
+pub fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, random_below(i as u64 + 1) as usize);
+    }
+}
+
+// Whether `text` matches a Redis style glob pattern: `*`, `?`, classes like
+// `[a-z]` or `[^abc]` and `\` escapes, compared byte by byte
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and of the text it currently swallows up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() {
+            let step = match pattern[p] {
+                b'*' => {
+                    star = Some((p, t));
+                    p += 1;
+                    continue;
+                },
+                b'?' => Some(p + 1),
+                b'[' => match glob_class(pattern, p, text[t]) {
+                    (true, next) => Some(next),
+                    _ => None,
+                },
+                b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(p + 2),
+                c => (c == text[t]).then_some(p + 1),
+            };
+            if let Some(next) = step {
+                p = next;
+                t += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                star = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            },
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+// Whether `c` matches the class starting at pattern[start] == '[', and the
+// pattern position after the class
+fn glob_class(pattern: &[u8], start: usize, c: u8) -> (bool, usize) {
+    let mut i = start + 1;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            let (low, high) = (pattern[i].min(pattern[i + 2]), pattern[i].max(pattern[i + 2]));
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+    (matched != negate, (i + 1).min(pattern.len()))
+}
+
+// Offsets of the first and last element (inclusive) selected by Redis
+// style start/stop indexes, negative ones counting from the end. None when
+// the window is empty.
+pub fn index_window(size: u64, start: i64, stop: i64) -> Option<(u64, u64)> {
+    let size = size as i64;
+    let start = if start < 0 { (size + start).max(0) } else { start };
+    let stop = if stop < 0 { size + stop } else { stop.min(size - 1) };
+    if start > stop || start >= size {
+        return None;
+    }
+    Some((start as u64, stop as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_window_clamps_to_the_size() {
+        assert_eq!(index_window(5, 0, -1), Some((0, 4)));
+        assert_eq!(index_window(5, -2, -1), Some((3, 4)));
+        assert_eq!(index_window(5, -10, 2), Some((0, 2)));
+        assert_eq!(index_window(5, 1, 100), Some((1, 4)));
+    }
+
+    #[test]
+    fn index_window_empty() {
+        assert_eq!(index_window(5, 3, 1), None);
+        assert_eq!(index_window(5, 5, 10), None);
+        assert_eq!(index_window(5, 0, -6), None);
+        assert_eq!(index_window(0, 0, -1), None);
+    }
 }
@@ -0,0 +1,683 @@
+use std::collections::{ HashMap, HashSet };
+use redis_module::{ RedisValue };
+use tikv_client::{ Error, Snapshot, Transaction };
+use crate::encoding::*;
+use crate::tikv::{ ReadBackend, clear_key, get_snapshot, type_keys, expect_type };
+use crate::txn::{ Reader, begin_txn, finish_txn };
+use crate::numeric::format_float;
+use crate::utils::{ glob_match, index_window, to_hex };
+
+// A sorted set is a meta entry of type ZSet, which also counts the members,
+// plus for each member an entry holding its score and an entry in the
+// score index, see encode_zset_member_key and encode_zset_score_key. Every
+// write updates both in the same transaction. The meta entry exists as
+// long as the set has members.
+
+const ZSET_PAGE_SIZE: u32 = 1000;
+
+// One end of a score interval, "(1.5" being 1.5 exclusive
+#[derive(Clone, Copy)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub inclusive: bool,
+}
+
+// OFFSET and COUNT of a LIMIT clause, a negative count meaning all
+#[derive(Clone, Copy)]
+pub struct Limit {
+    pub offset: i64,
+    pub count: i64,
+}
+
+impl Limit {
+    pub const ALL: Limit = Limit { offset: 0, count: -1 };
+
+    // Entries to skip and to take, None when the offset is negative
+    fn window(self) -> Option<(u64, u64)> {
+        if self.offset < 0 {
+            return None;
+        }
+        let take = if self.count < 0 { u64::MAX } else { self.count as u64 };
+        Some((self.offset as u64, take))
+    }
+}
+
+// Index range of the entries with scores between two bounds, None when
+// the interval is empty
+fn score_range(key: &[u8], min: ScoreBound, max: ScoreBound) -> Option<(Vec<u8>, Vec<u8>)> {
+    let start = zset_score_start(key, min.score, min.inclusive);
+    let end = zset_score_end(key, max.score, max.inclusive);
+    if start >= end {
+        return None;
+    }
+    Some((start, end))
+}
+
+// One end of a lexicographic interval: "-" and "+" for the lowest and
+// highest member, "[a" including and "(a" excluding the member a
+#[derive(Clone)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+// Range of member keys between two lexicographic bounds, None when the
+// interval is empty. Member keys sort by member, so this is the order the
+// score index has when all scores are equal.
+fn lex_range(key: &[u8], min: &LexBound, max: &LexBound) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (first, last) = zset_member_range(key);
+    let after = |member: &[u8]| {
+        let mut raw = encode_zset_member_key(key, member);
+        raw.push(0);
+        raw
+    };
+    let start = match min {
+        LexBound::Min => first,
+        LexBound::Max => return None,
+        LexBound::Inclusive(member) => encode_zset_member_key(key, member),
+        LexBound::Exclusive(member) => after(member),
+    };
+    let end = match max {
+        LexBound::Min => return None,
+        LexBound::Max => last,
+        LexBound::Inclusive(member) => after(member),
+        LexBound::Exclusive(member) => encode_zset_member_key(key, member),
+    };
+    if start >= end {
+        return None;
+    }
+    Some((start, end))
+}
+
+// What a range command selects: ranks, scores or members
+#[derive(Clone)]
+pub enum RangeBy {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+// Entries removed by ZREMRANGEBYSCORE and ZREMRANGEBYRANK
+#[derive(Clone, Copy)]
+pub enum RemoveRange {
+    Score(ScoreBound, ScoreBound),
+    Rank(i64, i64),
+}
+
+// ZADD flags: NX/XX gate on the member existing, GT/LT on the new score
+// comparing to the current one, CH counts changed members in the reply
+#[derive(Clone, Copy, Default)]
+pub struct ZAddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+}
+
+// Store a member's score in both keyspaces, dropping the index entry of
+// its previous score
+async fn write_score(txn: &mut Transaction, key: &[u8], member: &[u8], old: Option<f64>, score: f64) -> Result<(), Error> {
+    if let Some(old) = old {
+        txn.delete(encode_zset_score_key(key, old, member)).await?;
+    }
+    txn.put(encode_zset_score_key(key, score, member), Vec::new()).await?;
+    txn.put(encode_zset_member_key(key, member), encode_score(score).to_vec()).await
+}
+
+async fn save_meta(txn: &mut Transaction, key: &[u8], meta: &KeyMeta) -> Result<(), Error> {
+    if meta.size == 0 {
+        txn.delete(encode_meta_key(key)).await
+    } else {
+        txn.put(encode_meta_key(key), encode_meta(meta)).await
+    }
+}
+
+async fn zadd(txn: &mut Transaction, key: &[u8], opts: ZAddOptions, pairs: Vec<(f64, Vec<u8>)>) -> Result<i64, Error> {
+    let member_keys: Vec<Vec<u8>> = pairs.iter().map(|(_, member)| encode_zset_member_key(key, member)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(member_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let meta = expect_type(&found, key, DataType::ZSet)?;
+    let exists = meta.is_some();
+    let mut meta = meta.unwrap_or_else(|| KeyMeta::new(DataType::ZSet, 0));
+
+    // Scores as this command leaves them, a member may be given twice
+    let mut scores: HashMap<Vec<u8>, Option<f64>> = HashMap::new();
+    let (mut added, mut changed) = (0, 0);
+    for (member_key, (score, member)) in member_keys.into_iter().zip(pairs) {
+        let old = match scores.get(&member_key) {
+            Some(old) => *old,
+            None if exists => found.get(&member_key).and_then(|raw| decode_score(raw)),
+            None => None,
+        };
+        let write = match old {
+            None => !opts.xx,
+            Some(old) => !opts.nx && score != old && !(opts.gt && score <= old) && !(opts.lt && score >= old),
+        };
+        if !write {
+            continue;
+        }
+        write_score(txn, key, &member, old, score).await?;
+        scores.insert(member_key, Some(score));
+        if old.is_none() {
+            added += 1;
+        } else {
+            changed += 1;
+        }
+    }
+    if added > 0 {
+        meta.size += added as u64;
+        save_meta(txn, key, &meta).await?;
+    }
+    Ok(if opts.ch { added + changed } else { added })
+}
+
+// Add members or update their scores, replies how many were added (plus
+// how many were updated with CH)
+pub async fn do_async_zadd(key: &str, opts: ZAddOptions, pairs: Vec<(f64, String)>) -> Result<RedisValue, Error> {
+    let pairs = pairs.into_iter().map(|(score, member)| (score, member.into_bytes())).collect();
+    let mut txn = begin_txn().await?;
+    let res = zadd(&mut txn, key.as_bytes(), opts, pairs).await;
+    let count = finish_txn(txn, res).await?;
+    Ok(count.into())
+}
+
+// Score of a member, read with a point get of its member entry
+pub async fn do_async_zscore(key: &str, member: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let member_key = encode_zset_member_key(key, member.as_bytes());
+    let mut wanted = type_keys(key);
+    wanted.push(member_key.clone());
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    if expect_type(&found, key, DataType::ZSet)?.is_none() {
+        return Ok(RedisValue::Null);
+    }
+    Ok(match found.get(&member_key).and_then(|raw| decode_score(raw)) {
+        Some(score) => format_float(score).into(),
+        None => RedisValue::Null,
+    })
+}
+
+// Cardinality from the meta entry, which every write keeps up to date,
+// a single point read
+pub async fn do_async_zcard(key: &str, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(type_keys(key)).await?;
+    let size = expect_type(&found, key, DataType::ZSet)?.map_or(0, |m| m.size);
+    Ok((size as i64).into())
+}
+
+// Keys in [start, end), in order: the first `skip` are passed over, then
+// up to `take` are returned. Keys are scanned without values page by
+// page, skipping still reads them.
+async fn read_keys(reader: &mut Reader<'_>, range: (Vec<u8>, Vec<u8>), skip: u64, take: u64) -> Result<Vec<Vec<u8>>, Error> {
+    let (mut start, end) = range;
+    let mut keys = Vec::new();
+    let mut skipped = 0;
+    while (keys.len() as u64) < take {
+        let want = (skip - skipped).saturating_add(take - keys.len() as u64).min(ZSET_PAGE_SIZE as u64) as u32;
+        let page = reader.scan_keys((start.clone()..end.clone()).into(), want).await?;
+        let count = page.len();
+        for key in page.into_iter() {
+            start = key.into();
+            if skipped < skip {
+                skipped += 1;
+            } else if (keys.len() as u64) < take {
+                keys.push(start.clone());
+            }
+            start.push(0);
+        }
+        if count < want as usize {
+            break;
+        }
+    }
+    Ok(keys)
+}
+
+// Number of keys in [start, end), scanned without values page by page
+async fn count_keys(reader: &mut Reader<'_>, range: (Vec<u8>, Vec<u8>)) -> Result<u64, Error> {
+    let (mut start, end) = range;
+    let mut count = 0;
+    loop {
+        let page = reader.scan_keys((start.clone()..end.clone()).into(), ZSET_PAGE_SIZE).await?;
+        let len = page.len();
+        count += len as u64;
+        match page.into_iter().last() {
+            Some(last) if len == ZSET_PAGE_SIZE as usize => {
+                start = last.into();
+                start.push(0);
+            },
+            _ => return Ok(count),
+        }
+    }
+}
+
+// Entries of the score index in `range`, in (score, member) order, see
+// read_keys
+async fn read_index(reader: &mut Reader<'_>, key: &[u8], range: (Vec<u8>, Vec<u8>), skip: u64, take: u64) -> Result<Vec<(f64, Vec<u8>)>, Error> {
+    let keys = read_keys(reader, range, skip, take).await?;
+    Ok(keys.iter().filter_map(|k| decode_zset_score_key(key, k)).map(|(score, member)| (score, member.to_vec())).collect())
+}
+
+// Every entry of the sorted set in score order
+pub async fn read_scores(reader: &mut Reader<'_>, key: &[u8]) -> Result<Vec<(f64, Vec<u8>)>, Error> {
+    read_index(reader, key, zset_score_range(key), 0, u64::MAX).await
+}
+
+// Flat reply of members, each followed by its score with WITHSCORES
+pub fn entries_reply(entries: Vec<(f64, Vec<u8>)>, with_scores: bool) -> RedisValue {
+    let mut values = Vec::new();
+    for (score, member) in entries.into_iter() {
+        values.push(member.into());
+        if with_scores {
+            values.push(format_float(score).into());
+        }
+    }
+    RedisValue::Array(values)
+}
+
+// Entries between two ranks, both inclusive, in score order or from the
+// highest score with `rev`. Ranks below the window are scanned and passed
+// over, there is no way to seek to a rank, and the index only scans
+// upwards: ZRANGE reads up to `stop` entries, ZREVRANGE the set's size
+// minus `start`.
+async fn rank_entries(reader: &mut Reader<'_>, key: &[u8], size: u64, start: i64, stop: i64, rev: bool) -> Result<Vec<(f64, Vec<u8>)>, Error> {
+    let (first, last) = match index_window(size, start, stop) {
+        Some(window) => window,
+        None => return Ok(Vec::new()),
+    };
+    // Reverse ranks count from the other end of the same index
+    let (skip, take) = if rev { (size - 1 - last, last - first + 1) } else { (first, last - first + 1) };
+    let mut entries = read_index(reader, key, zset_score_range(key), skip, take).await?;
+    if rev {
+        entries.reverse();
+    }
+    Ok(entries)
+}
+
+// Entries with scores between `min` and `max`, turned into a range of the
+// score index, so only that range is scanned. With `rev` they come from
+// the highest score down: the index only scans upwards, so the whole
+// range is read and the LIMIT applied from its end.
+async fn score_entries(reader: &mut Reader<'_>, key: &[u8], min: ScoreBound, max: ScoreBound, rev: bool, limit: Limit) -> Result<Vec<(f64, Vec<u8>)>, Error> {
+    let (range, (skip, take)) = match (score_range(key, min, max), limit.window()) {
+        (Some(range), Some(window)) => (range, window),
+        _ => return Ok(Vec::new()),
+    };
+    if rev {
+        let mut entries = read_index(reader, key, range, 0, u64::MAX).await?;
+        entries.reverse();
+        return Ok(entries.into_iter().skip(skip as usize).take(take as usize).collect());
+    }
+    read_index(reader, key, range, skip, take).await
+}
+
+// Member keys between two lexicographic bounds, for sorted sets whose
+// members all have the same score. The interval is a key range of the
+// member keyspace, which sorts by member, so only matching members are
+// scanned. With `rev` the whole interval is read and LIMIT applied from
+// its end.
+async fn lex_member_keys(reader: &mut Reader<'_>, key: &[u8], min: &LexBound, max: &LexBound, rev: bool, limit: Limit) -> Result<Vec<Vec<u8>>, Error> {
+    let (range, (skip, take)) = match (lex_range(key, min, max), limit.window()) {
+        (Some(range), Some(window)) => (range, window),
+        _ => return Ok(Vec::new()),
+    };
+    if rev {
+        let mut keys = read_keys(reader, range, 0, u64::MAX).await?;
+        keys.reverse();
+        return Ok(keys.into_iter().skip(skip as usize).take(take as usize).collect());
+    }
+    read_keys(reader, range, skip, take).await
+}
+
+// Entries a range command selects, see rank_entries, score_entries and
+// lex_member_keys. The lexicographic range reads the scores of its
+// members with one more batch_get.
+async fn range_entries(reader: &mut Reader<'_>, key: &[u8], size: u64, by: &RangeBy, rev: bool, limit: Limit) -> Result<Vec<(f64, Vec<u8>)>, Error> {
+    match by {
+        RangeBy::Rank(start, stop) => rank_entries(reader, key, size, *start, *stop, rev).await,
+        RangeBy::Score(min, max) => score_entries(reader, key, *min, *max, rev, limit).await,
+        RangeBy::Lex(min, max) => {
+            let member_keys = lex_member_keys(reader, key, min, max, rev, limit).await?;
+            let found = reader.batch_get(member_keys.clone()).await?;
+            Ok(member_keys.iter().filter_map(|k| {
+                let score = found.get(k).and_then(|raw| decode_score(raw))?;
+                Some((score, decode_zset_member(key, k).to_vec()))
+            }).collect())
+        },
+    }
+}
+
+// Meta entry of a sorted set read from a snapshot, None when it is missing
+async fn snapshot_meta(snapshot: &mut Snapshot, key: &[u8]) -> Result<Option<KeyMeta>, Error> {
+    let found = Reader::Snapshot(snapshot).batch_get(type_keys(key)).await?;
+    expect_type(&found, key, DataType::ZSet)
+}
+
+// Members between two ranks, both inclusive, see rank_entries
+pub async fn do_async_zrange(key: &str, start: i64, stop: i64, rev: bool, with_scores: bool, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    let size = snapshot_meta(&mut snapshot, key).await?.map_or(0, |m| m.size);
+    let entries = rank_entries(&mut Reader::Snapshot(&mut snapshot), key, size, start, stop, rev).await?;
+    Ok(entries_reply(entries, with_scores))
+}
+
+// Members with scores between `min` and `max`, see score_entries
+pub async fn do_async_zrange_by_score(key: &str, min: ScoreBound, max: ScoreBound, rev: bool, with_scores: bool, limit: Limit, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    if snapshot_meta(&mut snapshot, key).await?.is_none() {
+        return Ok(RedisValue::Array(vec![]));
+    }
+    let entries = score_entries(&mut Reader::Snapshot(&mut snapshot), key, min, max, rev, limit).await?;
+    Ok(entries_reply(entries, with_scores))
+}
+
+// Members between two lexicographic bounds, see lex_member_keys
+pub async fn do_async_zrange_by_lex(key: &str, min: LexBound, max: LexBound, rev: bool, limit: Limit, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    if snapshot_meta(&mut snapshot, key).await?.is_none() {
+        return Ok(RedisValue::Array(vec![]));
+    }
+    let keys = lex_member_keys(&mut Reader::Snapshot(&mut snapshot), key, &min, &max, rev, limit).await?;
+    Ok(RedisValue::Array(keys.iter().map(|k| decode_zset_member(key, k).to_vec().into()).collect()))
+}
+
+async fn zrangestore(txn: &mut Transaction, destination: &[u8], source: &[u8], by: &RangeBy, rev: bool, limit: Limit) -> Result<i64, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(source)).await?;
+    let entries = match expect_type(&found, source, DataType::ZSet)? {
+        Some(meta) => range_entries(&mut Reader::Txn(txn), source, meta.size, by, rev, limit).await?,
+        None => Vec::new(),
+    };
+    // The entries were read first, so the source may be the destination
+    clear_key(txn, destination).await?;
+    for (score, member) in entries.iter() {
+        write_score(txn, destination, member, None, *score).await?;
+    }
+    let mut meta = KeyMeta::new(DataType::ZSet, 0);
+    meta.size = entries.len() as u64;
+    save_meta(txn, destination, &meta).await?;
+    Ok(entries.len() as i64)
+}
+
+// Store the entries a ZRANGE would return in `destination`, replacing
+// whatever it held, and reply how many there are. An empty range deletes
+// the destination. The source is read and the destination written in one
+// transaction.
+pub async fn do_async_zrangestore(destination: &str, source: &str, by: RangeBy, rev: bool, limit: Limit) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = zrangestore(&mut txn, destination.as_bytes(), source.as_bytes(), &by, rev, limit).await;
+    let count = finish_txn(txn, res).await?;
+    Ok(count.into())
+}
+
+// Drop entries, as read from the score index, from both keyspaces
+async fn delete_entries(txn: &mut Transaction, key: &[u8], entries: &[(f64, Vec<u8>)]) -> Result<(), Error> {
+    for (score, member) in entries.iter() {
+        txn.delete(encode_zset_score_key(key, *score, member)).await?;
+        txn.delete(encode_zset_member_key(key, member)).await?;
+    }
+    Ok(())
+}
+
+async fn zrem(txn: &mut Transaction, key: &[u8], mut members: Vec<Vec<u8>>) -> Result<i64, Error> {
+    let mut seen = HashSet::new();
+    members.retain(|member| seen.insert(member.clone()));
+    let member_keys: Vec<Vec<u8>> = members.iter().map(|member| encode_zset_member_key(key, member)).collect();
+    let mut wanted = type_keys(key);
+    wanted.extend(member_keys.iter().cloned());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let mut meta = match expect_type(&found, key, DataType::ZSet)? {
+        Some(meta) => meta,
+        None => return Ok(0),
+    };
+    // The score locates the member's index entry
+    let entries: Vec<(f64, Vec<u8>)> = member_keys.iter().zip(members)
+        .filter_map(|(member_key, member)| found.get(member_key).and_then(|raw| decode_score(raw)).map(|score| (score, member)))
+        .collect();
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    delete_entries(txn, key, &entries).await?;
+    meta.size -= entries.len() as u64;
+    save_meta(txn, key, &meta).await?;
+    Ok(entries.len() as i64)
+}
+
+// Remove members, replies how many existed
+pub async fn do_async_zrem(key: &str, members: Vec<String>) -> Result<RedisValue, Error> {
+    let members = members.into_iter().map(|member| member.into_bytes()).collect();
+    let mut txn = begin_txn().await?;
+    let res = zrem(&mut txn, key.as_bytes(), members).await;
+    let count = finish_txn(txn, res).await?;
+    Ok(count.into())
+}
+
+async fn zrem_range(txn: &mut Transaction, key: &[u8], which: RemoveRange) -> Result<i64, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = match expect_type(&found, key, DataType::ZSet)? {
+        Some(meta) => meta,
+        None => return Ok(0),
+    };
+    let (range, skip, take) = match which {
+        RemoveRange::Score(min, max) => match score_range(key, min, max) {
+            Some(range) => (range, 0, u64::MAX),
+            None => return Ok(0),
+        },
+        RemoveRange::Rank(start, stop) => match index_window(meta.size, start, stop) {
+            Some((first, last)) => (zset_score_range(key), first, last - first + 1),
+            None => return Ok(0),
+        },
+    };
+    let entries = read_index(&mut Reader::Txn(txn), key, range, skip, take).await?;
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    delete_entries(txn, key, &entries).await?;
+    meta.size -= entries.len() as u64;
+    save_meta(txn, key, &meta).await?;
+    Ok(entries.len() as i64)
+}
+
+// Remove the members with scores in an interval or ranks in a window,
+// replies how many were removed. Only the matching range of the score
+// index is scanned (by rank, the entries below `start` as well), its keys
+// name the members, so the deletes need no further reads and are sent
+// with the commit.
+pub async fn do_async_zrem_range(key: &str, which: RemoveRange) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = zrem_range(&mut txn, key.as_bytes(), which).await;
+    let count = finish_txn(txn, res).await?;
+    Ok(count.into())
+}
+
+async fn zincrby(txn: &mut Transaction, key: &[u8], increment: f64, member: &[u8]) -> Result<f64, Error> {
+    let member_key = encode_zset_member_key(key, member);
+    let mut wanted = type_keys(key);
+    wanted.push(member_key.clone());
+    let found = Reader::Txn(txn).batch_get(wanted).await?;
+    let mut meta = expect_type(&found, key, DataType::ZSet)?.unwrap_or_else(|| KeyMeta::new(DataType::ZSet, 0));
+    let old = found.get(&member_key).and_then(|raw| decode_score(raw));
+    let score = old.unwrap_or(0.0) + increment;
+    if score.is_nan() {
+        return Err(Error::StringError(String::from("ERR resulting score is not a number (NaN)")));
+    }
+    write_score(txn, key, member, old, score).await?;
+    if old.is_none() {
+        meta.size += 1;
+        save_meta(txn, key, &meta).await?;
+    }
+    Ok(score)
+}
+
+// Add `increment` to a member's score, a missing member starting from 0,
+// replies the new score. The old index entry is swapped for the new one
+// in the same transaction.
+pub async fn do_async_zincrby(key: &str, increment: f64, member: &str) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = zincrby(&mut txn, key.as_bytes(), increment, member.as_bytes()).await;
+    let score = finish_txn(txn, res).await?;
+    Ok(format_float(score).into())
+}
+
+// Rank of a member, counted from the lowest score or from the highest
+// with `rev`, and its score with WITHSCORE. TiKV keeps no counts, so the
+// index entries ranked below the member are scanned and counted: the cost
+// grows with the rank, up to the set's size. ZREVRANK counts the same
+// entries and subtracts from the size in the meta entry.
+pub async fn do_async_zrank(key: &str, member: &str, rev: bool, with_score: bool, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let member = member.as_bytes();
+    let member_key = encode_zset_member_key(key, member);
+    let mut wanted = type_keys(key);
+    wanted.push(member_key.clone());
+    let mut snapshot = get_snapshot(backend).await?;
+    let found = Reader::Snapshot(&mut snapshot).batch_get(wanted).await?;
+    let size = match expect_type(&found, key, DataType::ZSet)? {
+        Some(meta) => meta.size,
+        None => return Ok(RedisValue::Null),
+    };
+    let score = match found.get(&member_key).and_then(|raw| decode_score(raw)) {
+        Some(score) => score,
+        None => return Ok(RedisValue::Null),
+    };
+    let (start, _) = zset_score_range(key);
+    let below = count_keys(&mut Reader::Snapshot(&mut snapshot), (start, encode_zset_score_key(key, score, member))).await?;
+    let rank = if rev { size.saturating_sub(below + 1) } else { below };
+    if with_score {
+        return Ok(RedisValue::Array(vec![(rank as i64).into(), format_float(score).into()]));
+    }
+    Ok((rank as i64).into())
+}
+
+async fn zpop(txn: &mut Transaction, key: &[u8], count: u64, max: bool) -> Result<Vec<(f64, Vec<u8>)>, Error> {
+    let found = Reader::Txn(txn).batch_get(type_keys(key)).await?;
+    let mut meta = match expect_type(&found, key, DataType::ZSet)? {
+        Some(meta) => meta,
+        None => return Ok(Vec::new()),
+    };
+    let take = count.min(meta.size);
+    if take == 0 {
+        return Ok(Vec::new());
+    }
+    // The highest scores are the last entries of the index
+    let skip = if max { meta.size - take } else { 0 };
+    let mut entries = read_index(&mut Reader::Txn(txn), key, zset_score_range(key), skip, take).await?;
+    delete_entries(txn, key, &entries).await?;
+    meta.size -= entries.len() as u64;
+    save_meta(txn, key, &meta).await?;
+    if max {
+        entries.reverse();
+    }
+    Ok(entries)
+}
+
+// Remove and return up to `count` members (1 by default) with the lowest
+// scores, or the highest with `max`, as a flat list of members and
+// scores. Reading the entries and deleting them from both keyspaces
+// happen in one transaction. ZPOPMIN reads only the popped entries,
+// ZPOPMAX has to scan the whole index up to them.
+pub async fn do_async_zpop(key: &str, count: Option<u64>, max: bool) -> Result<RedisValue, Error> {
+    let mut txn = begin_txn().await?;
+    let res = zpop(&mut txn, key.as_bytes(), count.unwrap_or(1), max).await;
+    let entries = finish_txn(txn, res).await?;
+    Ok(entries_reply(entries, true))
+}
+
+// One ZSCAN step over the member keyspace, like do_async_sscan: up to
+// `count` members from `cursor` on, those matching `pattern` replied with
+// their scores. The reply cursor is the member key suffix to continue from
+// in hex, or "0" once the set is exhausted.
+pub async fn do_async_zscan(key: &str, cursor: Option<Vec<u8>>, pattern: Option<Vec<u8>>, count: u32, backend: ReadBackend) -> Result<RedisValue, Error> {
+    let key = key.as_bytes();
+    let mut snapshot = get_snapshot(backend).await?;
+    if snapshot_meta(&mut snapshot, key).await?.is_none() {
+        return Ok(RedisValue::Array(vec!["0".into(), RedisValue::Array(vec![])]));
+    }
+    let (first, end) = zset_member_range(key);
+    let start = match cursor {
+        Some(member) => encode_zset_member_key(key, &member),
+        None => first,
+    };
+    let pairs = Reader::Snapshot(&mut snapshot).scan((start..end).into(), count).await?;
+    let exhausted = pairs.len() < count as usize;
+    let entries: Vec<(f64, Vec<u8>)> = pairs.into_iter().filter_map(|p| {
+        let member = decode_zset_member(key, &Into::<Vec<u8>>::into(p.key().to_owned())).to_vec();
+        decode_score(p.value()).map(|score| (score, member))
+    }).collect();
+    let next: RedisValue = match entries.last() {
+        Some((_, last)) if !exhausted => {
+            let mut next = last.clone();
+            next.push(0);
+            to_hex(&next).into()
+        },
+        _ => "0".into(),
+    };
+    let entries = entries.into_iter()
+        .filter(|(_, member)| match &pattern {
+            Some(pattern) => glob_match(pattern, member),
+            None => true,
+        })
+        .collect();
+    Ok(RedisValue::Array(vec![next, entries_reply(entries, true)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(score: f64, inclusive: bool) -> ScoreBound {
+        ScoreBound { score, inclusive }
+    }
+
+    #[test]
+    fn score_range_edges() {
+        let (start, end) = score_range(b"z", bound(1.0, true), bound(1.0, true)).unwrap();
+        let entry = encode_zset_score_key(b"z", 1.0, b"m");
+        assert!(start <= entry && entry < end);
+        assert!(score_range(b"z", bound(1.0, false), bound(1.0, true)).is_none());
+        assert!(score_range(b"z", bound(1.0, true), bound(1.0, false)).is_none());
+        assert!(score_range(b"z", bound(2.0, true), bound(1.0, true)).is_none());
+
+        let (start, end) = score_range(b"z", bound(f64::NEG_INFINITY, true), bound(f64::INFINITY, true)).unwrap();
+        for score in [f64::NEG_INFINITY, -1.0, 0.0, f64::INFINITY] {
+            let entry = encode_zset_score_key(b"z", score, b"m");
+            assert!(start <= entry && entry < end);
+        }
+    }
+
+    #[test]
+    fn lex_range_edges() {
+        assert!(lex_range(b"z", &LexBound::Min, &LexBound::Max).is_some());
+        assert!(lex_range(b"z", &LexBound::Max, &LexBound::Max).is_none());
+        assert!(lex_range(b"z", &LexBound::Min, &LexBound::Min).is_none());
+
+        let a = LexBound::Inclusive(b"a".to_vec());
+        assert!(lex_range(b"z", &a, &a).is_some());
+        assert!(lex_range(b"z", &LexBound::Exclusive(b"a".to_vec()), &a).is_none());
+        assert!(lex_range(b"z", &a, &LexBound::Exclusive(b"a".to_vec())).is_none());
+
+        let (start, end) = lex_range(b"z", &a, &LexBound::Exclusive(b"b".to_vec())).unwrap();
+        let members: [&[u8]; 3] = [b"a", b"a\x00", b"azz"];
+        for member in members {
+            let entry = encode_zset_member_key(b"z", member);
+            assert!(start <= entry && entry < end);
+        }
+        assert!(encode_zset_member_key(b"z", b"b") >= end);
+    }
+
+    #[test]
+    fn limit_window() {
+        assert_eq!(Limit::ALL.window(), Some((0, u64::MAX)));
+        assert_eq!(Limit { offset: 2, count: 3 }.window(), Some((2, 3)));
+        assert_eq!(Limit { offset: 2, count: -5 }.window(), Some((2, u64::MAX)));
+        assert_eq!(Limit { offset: -1, count: 3 }.window(), None);
+    }
+}
@@ -0,0 +1,103 @@
+// End-to-end checks of each data type through redis-cli, against a Redis
+// server that has the module loaded and is connected to TiKV. They are
+// ignored by default, run them with `cargo test -- --ignored`.
+// REDIS_TIKV_PORT selects the server port, 6379 by default.
+
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Reply of one command, redis-cli printing one line per array element
+// when its output is not a terminal
+fn cli(args: &[&str]) -> Vec<String> {
+    let port = env::var("REDIS_TIKV_PORT").unwrap_or_else(|_| String::from("6379"));
+    let output = Command::new("redis-cli")
+        .arg("-p")
+        .arg(&port)
+        .args(args)
+        .output()
+        .expect("redis-cli must be on the PATH");
+    assert!(output.status.success(), "redis-cli {:?} failed", args);
+    String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect()
+}
+
+fn one(args: &[&str]) -> String {
+    cli(args).concat()
+}
+
+// Key no earlier run used, so runs against the same cluster don't collide
+fn fresh_key(name: &str) -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    format!("it:{}:{}", name, nanos)
+}
+
+#[test]
+#[ignore]
+fn string_commands() {
+    let key = fresh_key("string");
+    assert_eq!(one(&["tikv.set", &key, "hello"]), "OK");
+    assert_eq!(one(&["tikv.get", &key]), "hello");
+    assert_eq!(one(&["tikv.append", &key, " world"]), "11");
+    assert_eq!(one(&["tikv.strlen", &key]), "11");
+    assert_eq!(one(&["tikv.del", &key]), "OK");
+    assert_eq!(one(&["tikv.strlen", &key]), "0");
+}
+
+#[test]
+#[ignore]
+fn hash_commands() {
+    let key = fresh_key("hash");
+    assert_eq!(one(&["tikv.hset", &key, "f1", "v1", "f2", "v2"]), "2");
+    assert_eq!(one(&["tikv.hset", &key, "f1", "v3"]), "0");
+    assert_eq!(one(&["tikv.hget", &key, "f1"]), "v3");
+    assert_eq!(one(&["tikv.hlen", &key]), "2");
+    assert_eq!(one(&["tikv.hdel", &key, "f1", "nope"]), "1");
+    assert_eq!(one(&["tikv.hlen", &key]), "1");
+    assert_eq!(one(&["tikv.del", &key]), "OK");
+    assert_eq!(one(&["tikv.hlen", &key]), "0");
+}
+
+#[test]
+#[ignore]
+fn list_commands() {
+    let key = fresh_key("list");
+    assert_eq!(one(&["tikv.rpush", &key, "b", "c", "b"]), "3");
+    assert_eq!(one(&["tikv.lpush", &key, "a"]), "4");
+    assert_eq!(cli(&["tikv.lrange", &key, "0", "-1"]), vec!["a", "b", "c", "b"]);
+    assert_eq!(one(&["tikv.lpos", &key, "b", "RANK", "2"]), "3");
+    assert_eq!(one(&["tikv.lpop", &key]), "a");
+    assert_eq!(one(&["tikv.rpop", &key]), "b");
+    assert_eq!(one(&["tikv.llen", &key]), "2");
+    assert_eq!(one(&["tikv.del", &key]), "OK");
+    assert_eq!(one(&["tikv.llen", &key]), "0");
+}
+
+#[test]
+#[ignore]
+fn set_commands() {
+    let key = fresh_key("set");
+    assert_eq!(one(&["tikv.sadd", &key, "a", "b", "c", "a"]), "3");
+    assert_eq!(one(&["tikv.scard", &key]), "3");
+    assert_eq!(one(&["tikv.sismember", &key, "b"]), "1");
+    assert_eq!(cli(&["tikv.srandmember", &key, "-5"]).len(), 5);
+    let popped = one(&["tikv.spop", &key]);
+    assert!(["a", "b", "c"].contains(&popped.as_str()));
+    assert_eq!(one(&["tikv.sismember", &key, &popped]), "0");
+    assert_eq!(one(&["tikv.scard", &key]), "2");
+    assert_eq!(one(&["tikv.del", &key]), "OK");
+    assert_eq!(one(&["tikv.scard", &key]), "0");
+}
+
+#[test]
+#[ignore]
+fn zset_commands() {
+    let key = fresh_key("zset");
+    assert_eq!(one(&["tikv.zadd", &key, "2", "b", "1", "a", "3", "c"]), "3");
+    assert_eq!(cli(&["tikv.zrange", &key, "0", "-1"]), vec!["a", "b", "c"]);
+    assert_eq!(cli(&["tikv.zrangebyscore", &key, "(1", "+inf"]), vec!["b", "c"]);
+    assert_eq!(one(&["tikv.zrank", &key, "c"]), "2");
+    assert_eq!(one(&["tikv.zrem", &key, "b"]), "1");
+    assert_eq!(one(&["tikv.zcard", &key]), "2");
+    assert_eq!(one(&["tikv.del", &key]), "OK");
+    assert_eq!(one(&["tikv.zcard", &key]), "0");
+}